@@ -0,0 +1,13 @@
+#!/bin/bash
+
+# Runs the perf1-3 benchmarks from the mal test suite against a release build.
+# The perf tests load helpers from ../lib, vendored in mal/lib/ from the
+# upstream repository.
+
+BIN="${1:-stepA_mal}"
+
+cargo build --release --bin "$BIN" || exit 1
+cd ./mal/tests || exit 1
+for TEST in perf1 perf2 perf3; do
+  echo "$TEST: $(../../target/release/$BIN $TEST.mal | tail -1)"
+done
@@ -0,0 +1,251 @@
+use std::collections::HashSet;
+
+use crate::env::Env;
+use crate::symbol;
+use crate::types::*;
+
+// Resolves references to fn*, let* and catch* locals in a lambda body to
+// MalType::Local (depth, slot) addresses, so evaluating them indexes a frame
+// instead of searching the env chain by name.
+//
+// The analysis runs once when a fn* is evaluated, and nested fn* forms are
+// marked as analyzed along the way. Quoted forms, quasiquote templates and
+// macro calls are left as they are, since their symbols are data until the
+// macro has been expanded. Unresolved symbols keep being looked up by name,
+// which still finds locals, so leaving a form alone is always correct. A call
+// of a macro defined after the fn* does get analyzed, so its arguments are
+// put back with unresolve before the macro sees them.
+//
+// The compiler resolves locals the same way, through Scopes.
+pub(crate) struct Scopes<'a> {
+  // Names bound in each runtime frame, from the outermost inwards
+  frames: Vec<Vec<Symbol>>,
+  // Names the body defines with def!, which may shadow locals at run time
+  dynamic: HashSet<Symbol>,
+  env: &'a Env,
+}
+
+impl<'a> Scopes<'a> {
+  // Scopes for `form` evaluated in `env`, starting from the frames of `env`
+  pub(crate) fn new(form: &MalType, env: &'a Env) -> Scopes<'a> {
+    let mut dynamic = HashSet::new();
+    collect_defs(form, &mut dynamic);
+    Scopes {
+      frames: env.scopes(),
+      dynamic,
+      env,
+    }
+  }
+
+  pub(crate) fn push(&mut self, names: Vec<Symbol>) {
+    self.frames.push(names);
+  }
+
+  pub(crate) fn bind(&mut self, name: Symbol) {
+    self.frames.last_mut().unwrap().push(name);
+  }
+
+  pub(crate) fn pop(&mut self) {
+    self.frames.pop();
+  }
+
+  pub(crate) fn depth(&self) -> usize {
+    self.frames.len()
+  }
+
+  // Drops the frames pushed since depth() returned `depth`
+  pub(crate) fn truncate(&mut self, depth: usize) {
+    self.frames.truncate(depth);
+  }
+
+  pub(crate) fn env(&self) -> &'a Env {
+    self.env
+  }
+
+  pub(crate) fn resolve(&self, sym: Symbol) -> Option<MalType> {
+    if self.dynamic.contains(&sym) {
+      return None;
+    }
+    for (depth, frame) in self.frames.iter().rev().enumerate() {
+      if let Some(slot) = frame.iter().rposition(|name| *name == sym) {
+        return Some(MalType::Local(sym, depth, slot));
+      }
+    }
+    None
+  }
+
+  // Macros are resolved against the env the fn* is evaluated in
+  pub(crate) fn is_macro(&self, sym: Symbol) -> bool {
+    self.resolve(sym).is_none() && matches!(self.env.get(sym), Ok(value) if value.is_macro())
+  }
+}
+
+// Analyzes the parameters and body following the fn* of a form evaluated in `env`
+pub fn analyze_fn(mut list: MalList, env: &Env) -> MalList {
+  let mut scopes = Scopes::new(&MalType::List(list.clone(), None, None), env);
+  list.push_front(MalType::Symbol(symbol::FN, None));
+  let mut list = analyze_lambda(list, &mut scopes);
+  list.pop_front();
+  list
+}
+
+// (fn* params body), where the body sees the parameters
+fn analyze_lambda(mut list: MalList, scopes: &mut Scopes) -> MalList {
+  let params = match list.get(1).and_then(|params| params.list_value()) {
+    Some(params) => params,
+    None => return list,
+  };
+  let names = params
+    .iter()
+    .filter_map(|param| param.symbol_value())
+    .filter(|name| *name != symbol::AMPERSAND)
+    .collect();
+  let body = list.split_off(2);
+  scopes.push(names);
+  list.append(analyze_all(body, scopes));
+  scopes.pop();
+  list.set(0, MalType::Symbol(symbol::FN_ANALYZED, None));
+  list
+}
+
+fn analyze(form: MalType, scopes: &mut Scopes) -> MalType {
+  match form {
+    MalType::Symbol(sym, _) => scopes.resolve(sym).unwrap_or(form),
+    MalType::List(list, span, meta) => MalType::List(analyze_list(list, scopes), span, meta),
+    MalType::Vector(list, span, meta) => MalType::Vector(analyze_all(list, scopes), span, meta),
+    MalType::HashMap(map, meta) => MalType::HashMap(
+      map
+        .into_iter()
+        .map(|(key, value)| (key, analyze(value, scopes)))
+        .collect(),
+      meta,
+    ),
+    _ => form,
+  }
+}
+
+fn analyze_all(list: MalList, scopes: &mut Scopes) -> MalList {
+  list.into_iter().map(|form| analyze(form, scopes)).collect()
+}
+
+fn analyze_list(list: MalList, scopes: &mut Scopes) -> MalList {
+  let head = match list.front() {
+    Some(MalType::Symbol(sym, _)) => *sym,
+    _ => return analyze_all(list, scopes),
+  };
+  match head {
+    symbol::QUOTE | symbol::QUASIQUOTE | symbol::MACROEXPAND | symbol::FN_ANALYZED => list,
+    symbol::DEF | symbol::DEFMACRO => analyze_from(list, 2, scopes),
+    symbol::DO | symbol::IF => analyze_from(list, 1, scopes),
+    symbol::LET => analyze_let(list, scopes),
+    symbol::FN => analyze_lambda(list, scopes),
+    symbol::TRY => analyze_try(list, scopes),
+    sym if scopes.is_macro(sym) => list,
+    _ => analyze_all(list, scopes),
+  }
+}
+
+// Analyzes the items of `list` from `start` on, leaving the ones before alone
+fn analyze_from(mut list: MalList, start: usize, scopes: &mut Scopes) -> MalList {
+  if list.len() > start {
+    let rest = list.split_off(start);
+    list.append(analyze_all(rest, scopes));
+  }
+  list
+}
+
+// (let* bindings body), where each binding sees the ones before it
+fn analyze_let(mut list: MalList, scopes: &mut Scopes) -> MalList {
+  let bindings = match list.get(1) {
+    Some(MalType::List(bindings, _, _)) | Some(MalType::Vector(bindings, _, _)) => bindings.clone(),
+    _ => return list,
+  };
+  let is_valid = bindings.len() % 2 == 0
+    && bindings
+      .iter()
+      .step_by(2)
+      .all(|name| name.symbol_value().is_some());
+  if !is_valid {
+    return list;
+  }
+
+  scopes.push(vec![]);
+  let mut analyzed = MalList::new();
+  let mut bindings = bindings.into_iter();
+  while let (Some(name), Some(value)) = (bindings.next(), bindings.next()) {
+    let value = analyze(value, scopes);
+    scopes.bind(name.symbol_value().unwrap());
+    analyzed.push_back(name);
+    analyzed.push_back(value);
+  }
+  let analyzed = match &list[1] {
+    MalType::List(_, span, _) => MalType::List(analyzed, span.clone(), None),
+    bindings => MalType::Vector(analyzed, bindings.span(), None),
+  };
+  list.set(1, analyzed);
+  let list = analyze_from(list, 2, scopes);
+  scopes.pop();
+  list
+}
+
+// (try* body (catch* name handler)), where the handler sees `name`
+fn analyze_try(list: MalList, scopes: &mut Scopes) -> MalList {
+  let mut list = match list.get(2) {
+    Some(MalType::List(catch, _, _)) if is_catch(catch) => list,
+    _ => return analyze_from(list, 1, scopes),
+  };
+  if let MalType::List(catch, span, _) = list[2].clone() {
+    let name = catch[1].symbol_value().unwrap();
+    scopes.push(vec![name]);
+    let catch = analyze_from(catch, 2, scopes);
+    scopes.pop();
+    list.set(2, MalType::List(catch, span, None));
+  }
+  list.set(1, analyze(list[1].clone(), scopes));
+  list
+}
+
+// Undoes the analysis of `form`, turning locals back into symbols and
+// analyzed fn* forms back into fn*
+pub(crate) fn unresolve(form: MalType) -> MalType {
+  match form {
+    MalType::Local(sym, _, _) => MalType::Symbol(sym, None),
+    MalType::Symbol(sym, span) if sym == symbol::FN_ANALYZED => MalType::Symbol(symbol::FN, span),
+    MalType::List(list, span, meta) => {
+      MalType::List(list.into_iter().map(unresolve).collect(), span, meta)
+    }
+    MalType::Vector(list, span, meta) => {
+      MalType::Vector(list.into_iter().map(unresolve).collect(), span, meta)
+    }
+    MalType::HashMap(map, meta) => MalType::HashMap(
+      map
+        .into_iter()
+        .map(|(key, value)| (key, unresolve(value)))
+        .collect(),
+      meta,
+    ),
+    _ => form,
+  }
+}
+
+pub(crate) fn is_catch(list: &MalList) -> bool {
+  list.len() >= 3 && list[0].is_symbol_named(symbol::CATCH) && list[1].is_symbol()
+}
+
+// Collects the names defined with def! or defmacro! anywhere in `form`
+fn collect_defs(form: &MalType, defs: &mut HashSet<Symbol>) {
+  if let MalType::List(list, _, _) | MalType::Vector(list, _, _) = form {
+    match list.front() {
+      Some(head) if head.is_symbol_named(symbol::QUOTE) => return,
+      Some(head) if head.is_symbol_named(symbol::DEF) || head.is_symbol_named(symbol::DEFMACRO) => {
+        if let Some(name) = list.get(1).and_then(|name| name.symbol_value()) {
+          defs.insert(name);
+        }
+      }
+      _ => (),
+    }
+    for item in list.iter() {
+      collect_defs(item, defs);
+    }
+  }
+}
@@ -41,27 +41,23 @@ fn lookup(env: &ReplEnv, sym: &str) -> Result<MalType, MalError> {
   }
 }
 
-fn eval_hash_map(list: Vec<MalType>, env: &mut ReplEnv) -> Result<Vec<MalType>, MalError> {
-  let mut new_list = Vec::new();
-  for (i, item) in list.iter().enumerate() {
-    if i % 2 != 0 {
-      new_list.push(eval(item.to_owned(), env)?);
-    } else {
-      new_list.push(item.to_owned());
-    }
+fn eval_hash_map(map: MalHashMap, env: &mut ReplEnv) -> Result<MalHashMap, MalError> {
+  let mut new_map = MalHashMap::new();
+  for (key, value) in map {
+    new_map.insert(key, eval(value, env)?);
   }
-  Ok(new_list)
+  Ok(new_map)
 }
 
 fn eval_ast(input: MalType, env: &mut ReplEnv) -> Result<MalType, MalError> {
-  let mut eval_list = |list: Vec<MalType>| -> Result<Vec<MalType>, MalError> {
-    list.into_iter().map(|v| eval(v.clone(), env)).collect()
+  let mut eval_list = |list: MalList| -> Result<MalList, MalError> {
+    list.into_iter().map(|v| eval(v, env)).collect()
   };
   let value = match input {
-    MalType::Symbol(sym) => lookup(env, &sym)?,
-    MalType::List(list) => MalType::List(eval_list(list)?),
-    MalType::Vector(list) => MalType::Vector(eval_list(list)?),
-    MalType::HashMap(list) => MalType::HashMap(eval_hash_map(list, env)?),
+    MalType::Symbol(sym, _) => lookup(env, &sym.name())?,
+    MalType::List(list, _, _) => MalType::List(eval_list(list)?, None, None),
+    MalType::Vector(list, _, _) => MalType::Vector(eval_list(list)?, None, None),
+    MalType::HashMap(map, _) => MalType::HashMap(eval_hash_map(map, env)?, None),
     _ => input,
   };
   Ok(value)
@@ -72,14 +68,14 @@ fn eval(input: MalType, env: &mut ReplEnv) -> Result<MalType, MalError> {
     return eval_ast(input, env);
   }
   if let Some(list) = input.list_value() {
-    if list.len() == 0 {
+    if list.is_empty() {
       return Ok(input);
     }
   }
   let new_input = eval_ast(input, env)?;
   if let Some(mut list) = new_input.list_value() {
     let result = match list.remove(0) {
-      MalType::Function(malfunc) => (malfunc.func)(&mut list, malfunc.env)?,
+      MalType::Function(malfunc) => malfunc.call(&mut list.into_iter().collect())?,
       _ => return Err(MalError::not_a_function()),
     };
     Ok(result)
@@ -106,34 +102,10 @@ fn rep(env: &mut ReplEnv) -> Result<RepResult, MalError> {
 
 fn main() {
   let mut env = HashMap::new();
-  env.insert(
-    "+".to_string(),
-    MalType::Function(MalFunc {
-      func: core::plus,
-      env: None,
-    }),
-  );
-  env.insert(
-    "-".to_string(),
-    MalType::Function(MalFunc {
-      func: core::minus,
-      env: None,
-    }),
-  );
-  env.insert(
-    "*".to_string(),
-    MalType::Function(MalFunc {
-      func: core::multiply,
-      env: None,
-    }),
-  );
-  env.insert(
-    "/".to_string(),
-    MalType::Function(MalFunc {
-      func: core::divide,
-      env: None,
-    }),
-  );
+  env.insert("+".to_string(), core::CORE_FUNCTIONS["+"].to_mal());
+  env.insert("-".to_string(), core::CORE_FUNCTIONS["-"].to_mal());
+  env.insert("*".to_string(), core::CORE_FUNCTIONS["*"].to_mal());
+  env.insert("/".to_string(), core::CORE_FUNCTIONS["/"].to_mal());
 
   loop {
     match rep(&mut env) {
@@ -2,6 +2,7 @@ use std::io::{self, Write};
 
 use mal_rust::core;
 use mal_rust::env::Env;
+use mal_rust::symbol;
 use mal_rust::types::*;
 use mal_rust::{printer, reader};
 
@@ -31,19 +32,15 @@ fn read() -> Result<ReadResult, MalError> {
   Ok(out)
 }
 
-fn eval_hash_map(list: Vec<MalType>, env: &mut Env) -> Result<Vec<MalType>, MalError> {
-  let mut new_list = Vec::new();
-  for (i, item) in list.iter().enumerate() {
-    if i % 2 != 0 {
-      new_list.push(eval(item.to_owned(), env)?);
-    } else {
-      new_list.push(item.to_owned());
-    }
+fn eval_hash_map(map: MalHashMap, env: &mut Env) -> Result<MalHashMap, MalError> {
+  let mut new_map = MalHashMap::new();
+  for (key, value) in map {
+    new_map.insert(key, eval(value, env)?);
   }
-  Ok(new_list)
+  Ok(new_map)
 }
 
-fn def(env: &mut Env, list: Vec<MalType>) -> MalResult {
+fn def(env: &mut Env, list: MalList) -> MalResult {
   let key = match list.get(1) {
     Some(k) => k.symbol_value().unwrap(),
     None => return Err(MalError::unknown()),
@@ -52,15 +49,15 @@ fn def(env: &mut Env, list: Vec<MalType>) -> MalResult {
     Some(k) => eval(k.to_owned(), env)?,
     None => return Err(MalError::unknown()),
   };
-  env.set(&key, value.clone());
+  env.set(key, value.clone());
   Ok(value)
 }
 
-fn let_star(env: &mut Env, list: &mut Vec<MalType>) -> MalResult {
+fn let_star(env: &mut Env, list: &mut MalList) -> MalResult {
   let mut new_env = Env::new(Some(env.to_owned()));
   list.remove(0); // Remove "let*"
   let mut bindings = match list.remove(0) {
-    MalType::List(list) | MalType::Vector(list) => list,
+    MalType::List(list, _, _) | MalType::Vector(list, _, _) => list,
     _ => return Err(MalError::wrong_arguments("Expected list or vector")),
   };
 
@@ -69,30 +66,30 @@ fn let_star(env: &mut Env, list: &mut Vec<MalType>) -> MalResult {
   }
 
   loop {
-    if bindings.len() == 0 {
+    if bindings.is_empty() {
       break;
     }
-    let symbol = match bindings.remove(0) {
-      MalType::Symbol(sym) => sym,
+    let symbol = match bindings.pop_front().unwrap() {
+      MalType::Symbol(sym, _) => sym,
       _ => return Err(MalError::wrong_arguments("Expected symbol")),
     };
 
-    let value = eval(bindings.remove(0), &mut new_env)?;
-    new_env.set(&symbol, value);
+    let value = eval(bindings.pop_front().unwrap(), &mut new_env)?;
+    new_env.set(symbol, value);
   }
 
-  Ok(eval(list.remove(0), &mut new_env)?)
+  eval(list.remove(0), &mut new_env)
 }
 
 fn eval_ast(input: MalType, env: &mut Env) -> Result<MalType, MalError> {
-  let mut eval_list = |list: Vec<MalType>| -> Result<Vec<MalType>, MalError> {
-    list.into_iter().map(|v| eval(v.clone(), env)).collect()
+  let mut eval_list = |list: MalList| -> Result<MalList, MalError> {
+    list.into_iter().map(|v| eval(v, env)).collect()
   };
   let value = match input {
-    MalType::Symbol(sym) => env.get(&sym)?,
-    MalType::List(list) => MalType::List(eval_list(list)?),
-    MalType::Vector(list) => MalType::Vector(eval_list(list)?),
-    MalType::HashMap(list) => MalType::HashMap(eval_hash_map(list, env)?),
+    MalType::Symbol(sym, _) => env.get(sym)?,
+    MalType::List(list, _, _) => MalType::List(eval_list(list)?, None, None),
+    MalType::Vector(list, _, _) => MalType::Vector(eval_list(list)?, None, None),
+    MalType::HashMap(map, _) => MalType::HashMap(eval_hash_map(map, env)?, None),
     _ => input,
   };
   Ok(value)
@@ -103,20 +100,20 @@ fn eval(input: MalType, env: &mut Env) -> Result<MalType, MalError> {
     return eval_ast(input, env);
   }
   if let Some(list) = input.list_value() {
-    if list.len() == 0 {
+    if list.is_empty() {
       return Ok(input);
     }
   }
 
   if let Some(mut list) = input.list_value() {
     let result = match &list[0] {
-      MalType::Symbol(sym) => match sym.as_str() {
-        "def!" => def(env, list)?,
-        "let*" => let_star(env, &mut list)?,
+      MalType::Symbol(sym, _) => match *sym {
+        symbol::DEF => def(env, list)?,
+        symbol::LET => let_star(env, &mut list)?,
         _ => {
           let mut list = eval_ast(input, env)?.list_value().unwrap();
           match list.remove(0) {
-            MalType::Function(malfunc) => (malfunc.func)(&mut list, malfunc.env)?,
+            MalType::Function(malfunc) => malfunc.call(&mut list.into_iter().collect())?,
             _ => return Err(MalError::not_a_function()),
           }
         }
@@ -147,34 +144,10 @@ fn rep(env: &mut Env) -> Result<RepResult, MalError> {
 
 fn main() {
   let mut env = Env::new(None);
-  env.set(
-    "+",
-    MalType::Function(MalFunc {
-      func: core::plus,
-      env: None,
-    }),
-  );
-  env.set(
-    "-",
-    MalType::Function(MalFunc {
-      func: core::minus,
-      env: None,
-    }),
-  );
-  env.set(
-    "/",
-    MalType::Function(MalFunc {
-      func: core::divide,
-      env: None,
-    }),
-  );
-  env.set(
-    "*",
-    MalType::Function(MalFunc {
-      func: core::multiply,
-      env: None,
-    }),
-  );
+  env.set("+", core::CORE_FUNCTIONS["+"].to_mal());
+  env.set("-", core::CORE_FUNCTIONS["-"].to_mal());
+  env.set("/", core::CORE_FUNCTIONS["/"].to_mal());
+  env.set("*", core::CORE_FUNCTIONS["*"].to_mal());
 
   loop {
     match rep(&mut env) {
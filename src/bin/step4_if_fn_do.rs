@@ -2,6 +2,7 @@ use std::io::{self, Write};
 
 use mal_rust::core;
 use mal_rust::env::Env;
+use mal_rust::symbol;
 use mal_rust::types::*;
 use mal_rust::{printer, reader};
 
@@ -31,19 +32,15 @@ fn read() -> Result<ReadResult, MalError> {
   Ok(out)
 }
 
-fn eval_hash_map(list: Vec<MalType>, env: &mut Env) -> Result<Vec<MalType>, MalError> {
-  let mut new_list = Vec::new();
-  for (i, item) in list.iter().enumerate() {
-    if i % 2 != 0 {
-      new_list.push(eval(item.to_owned(), env)?);
-    } else {
-      new_list.push(item.to_owned());
-    }
+fn eval_hash_map(map: MalHashMap, env: &mut Env) -> Result<MalHashMap, MalError> {
+  let mut new_map = MalHashMap::new();
+  for (key, value) in map {
+    new_map.insert(key, eval(value, env)?);
   }
-  Ok(new_list)
+  Ok(new_map)
 }
 
-fn def(env: &mut Env, list: Vec<MalType>) -> MalResult {
+fn def(env: &mut Env, list: MalList) -> MalResult {
   let key = match list.get(0) {
     Some(k) => k.symbol_value().unwrap(),
     None => return Err(MalError::unknown()),
@@ -52,14 +49,14 @@ fn def(env: &mut Env, list: Vec<MalType>) -> MalResult {
     Some(k) => eval(k.to_owned(), env)?,
     None => return Err(MalError::unknown()),
   };
-  env.set(&key, value.clone());
+  env.set(key, value.clone());
   Ok(value)
 }
 
-fn let_star(env: &mut Env, list: &mut Vec<MalType>) -> MalResult {
+fn let_star(env: &mut Env, list: &mut MalList) -> MalResult {
   let mut new_env = Env::new(Some(env.to_owned()));
   let mut bindings = match list.remove(0) {
-    MalType::List(list) | MalType::Vector(list) => list,
+    MalType::List(list, _, _) | MalType::Vector(list, _, _) => list,
     _ => return Err(MalError::wrong_arguments("Expected list or vector")),
   };
 
@@ -68,23 +65,23 @@ fn let_star(env: &mut Env, list: &mut Vec<MalType>) -> MalResult {
   }
 
   loop {
-    if bindings.len() == 0 {
+    if bindings.is_empty() {
       break;
     }
-    let symbol = match bindings.remove(0) {
-      MalType::Symbol(sym) => sym,
+    let symbol = match bindings.pop_front().unwrap() {
+      MalType::Symbol(sym, _) => sym,
       _ => return Err(MalError::wrong_arguments("Expected symbol")),
     };
 
-    let value = eval(bindings.remove(0), &mut new_env)?;
-    new_env.set(&symbol, value);
+    let value = eval(bindings.pop_front().unwrap(), &mut new_env)?;
+    new_env.set(symbol, value);
   }
 
-  Ok(eval(list.remove(0), &mut new_env)?)
+  eval(list.remove(0), &mut new_env)
 }
 
-fn eval_do(input: &mut Vec<MalType>, env: &mut Env) -> MalResult {
-  let input = MalType::List(input.to_owned());
+fn eval_do(input: &mut MalList, env: &mut Env) -> MalResult {
+  let input = MalType::List(input.to_owned(), None, None);
   let list = eval_ast(input, env)?
     .list_value()
     .ok_or(MalError::unknown())?;
@@ -94,23 +91,23 @@ fn eval_do(input: &mut Vec<MalType>, env: &mut Env) -> MalResult {
   }
 }
 
-fn eval_if(input: &mut Vec<MalType>, env: &mut Env) -> MalResult {
+fn eval_if(input: &MalList, env: &mut Env) -> MalResult {
   if input.len() < 2 {
     return Err(MalError::unknown());
   }
 
   let condition = eval(input[0].to_owned(), env)?;
   if condition.is_truthy() {
-    return eval(input[1].to_owned(), env);
+    eval(input[1].to_owned(), env)
   } else {
-    return match input.get(2) {
+    match input.get(2) {
       Some(falsey) => eval(falsey.to_owned(), env),
       None => Ok(MalType::Nil),
-    };
+    }
   }
 }
 
-fn eval_fn_star(input: &mut Vec<MalType>, env: &mut Env) -> MalResult {
+fn eval_fn_star(input: &MalList, env: &mut Env) -> MalResult {
   if input.len() < 2 {
     return Err(MalError::generic("Not enough args to fn*, expecting 2"));
   }
@@ -121,6 +118,10 @@ fn eval_fn_star(input: &mut Vec<MalType>, env: &mut Env) -> MalResult {
       env: env.clone(),
       args,
       body: vec![body],
+      is_macro: false,
+      meta: Box::new(MalType::Nil),
+      name: None,
+      code: None,
     });
     Ok(lambda)
   } else {
@@ -131,31 +132,26 @@ fn eval_fn_star(input: &mut Vec<MalType>, env: &mut Env) -> MalResult {
   }
 }
 
-fn call_lambda(
-  parent: Env,
-  binds: Vec<MalType>,
-  mut body: Vec<MalType>,
-  args: Vec<MalType>,
-) -> MalResult {
-  let binds: Vec<String> = binds
+fn call_lambda(parent: Env, binds: MalList, mut body: Vec<MalType>, args: MalList) -> MalResult {
+  let binds: Vec<Symbol> = binds
     .into_iter()
     .filter_map(|val| val.symbol_value())
     .collect();
-  let mut env = Env::new_with_bindings(Some(parent), binds, args);
+  let mut env = Env::new_with_bindings(Some(parent), "fn*", binds, args)?;
   let expr = body.remove(0);
   eval(expr, &mut env)
 }
 
 fn eval_ast(input: MalType, env: &mut Env) -> MalResult {
   // println!("EVAL AST Input: {}", printer::print_str(&input, true));
-  let mut eval_list = |list: Vec<MalType>| -> Result<Vec<MalType>, MalError> {
-    list.into_iter().map(|v| eval(v.clone(), env)).collect()
+  let mut eval_list = |list: MalList| -> Result<MalList, MalError> {
+    list.into_iter().map(|v| eval(v, env)).collect()
   };
   let value = match input {
-    MalType::Symbol(sym) => env.get(&sym)?,
-    MalType::List(list) => MalType::List(eval_list(list)?),
-    MalType::Vector(list) => MalType::Vector(eval_list(list)?),
-    MalType::HashMap(list) => MalType::HashMap(eval_hash_map(list, env)?),
+    MalType::Symbol(sym, _) => env.get(sym)?,
+    MalType::List(list, _, _) => MalType::List(eval_list(list)?, None, None),
+    MalType::Vector(list, _, _) => MalType::Vector(eval_list(list)?, None, None),
+    MalType::HashMap(map, _) => MalType::HashMap(eval_hash_map(map, env)?, None),
     _ => input,
   };
   Ok(value)
@@ -166,18 +162,18 @@ fn eval(input: MalType, env: &mut Env) -> MalResult {
   if !input.is_list() {
     // println!("EVAL NOT LIST");
     eval_ast(input, env)
-  } else if input.list_value().unwrap().len() == 0 {
+  } else if input.list_value().unwrap().is_empty() {
     Ok(input)
   } else if is_special_form(&input) {
     // println!("EVAL Special Form");
     if let Some(mut list) = input.list_value() {
-      if let MalType::Symbol(sym) = list.remove(0) {
-        match sym.as_str() {
-          "def!" => def(env, list),
-          "let*" => let_star(env, &mut list),
-          "do" => eval_do(&mut list, env),
-          "if" => eval_if(&mut list, env),
-          "fn*" => eval_fn_star(&mut list, env),
+      if let MalType::Symbol(sym, _) = list.remove(0) {
+        match sym {
+          symbol::DEF => def(env, list),
+          symbol::LET => let_star(env, &mut list),
+          symbol::DO => eval_do(&mut list, env),
+          symbol::IF => eval_if(&list, env),
+          symbol::FN => eval_fn_star(&list, env),
           _ => Err(MalError::generic("Unknown special symbol")),
         }
       } else {
@@ -190,28 +186,28 @@ fn eval(input: MalType, env: &mut Env) -> MalResult {
     // Must be a function or lambda call
     let mut list = eval_ast(input, env)?.list_value().unwrap();
     match list.remove(0) {
-      MalType::Function(MalFunc { func, env, .. }) => func(&mut list, env),
+      MalType::Function(func) => func.call(&mut list.into_iter().collect()),
       MalType::Lambda(MalLambda {
         env, args, body, ..
       }) => {
         // println!("EVAL Calling lambda");
         call_lambda(env.clone(), args.clone(), body.clone(), list)
       }
-      _ => return Err(MalError::not_a_function()),
+      _ => Err(MalError::not_a_function()),
     }
   }
 }
 
 fn is_special_form(input: &MalType) -> bool {
   if let Some(list) = input.list_value() {
-    if list.len() == 0 {
+    if list.is_empty() {
       return false;
     }
     if let Some(sym) = list[0].symbol_value() {
-      return match sym.as_str() {
-        "def!" | "let*" | "do" | "if" | "fn*" => true,
-        _ => false,
-      };
+      return matches!(
+        sym,
+        symbol::DEF | symbol::LET | symbol::DO | symbol::IF | symbol::FN
+      );
     }
   }
   false
@@ -236,14 +232,8 @@ fn rep(env: &mut Env) -> Result<RepResult, MalError> {
 fn main() {
   let mut env = Env::new(None);
   // Add native lib functions
-  for (sym, func) in &*core::CORE_FUNCTIONS {
-    env.set(
-      sym,
-      MalType::Function(MalFunc {
-        func: *func,
-        env: None,
-      }),
-    )
+  for (sym, def) in &*core::CORE_FUNCTIONS {
+    env.set_native(sym, def.arity, def.func);
   }
   // Eval stdlib mal functions
   let ast = reader::read_str(String::from("(def! not (fn* (a) (if a false true)))")).unwrap();
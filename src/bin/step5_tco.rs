@@ -2,6 +2,7 @@ use std::io::{self, Write};
 
 use mal_rust::core;
 use mal_rust::env::Env;
+use mal_rust::symbol;
 use mal_rust::types::*;
 use mal_rust::{printer, reader};
 
@@ -31,19 +32,15 @@ fn read() -> Result<ReadResult, MalError> {
   Ok(out)
 }
 
-fn eval_hash_map(list: Vec<MalType>, env: &mut Env) -> Result<Vec<MalType>, MalError> {
-  let mut new_list = Vec::new();
-  for (i, item) in list.iter().enumerate() {
-    if i % 2 != 0 {
-      new_list.push(eval(item.to_owned(), env)?);
-    } else {
-      new_list.push(item.to_owned());
-    }
+fn eval_hash_map(map: MalHashMap, env: &mut Env) -> Result<MalHashMap, MalError> {
+  let mut new_map = MalHashMap::new();
+  for (key, value) in map {
+    new_map.insert(key, eval(value, env)?);
   }
-  Ok(new_list)
+  Ok(new_map)
 }
 
-fn def(env: &mut Env, list: Vec<MalType>) -> MalResult {
+fn def(env: &mut Env, list: MalList) -> MalResult {
   let key = match list.get(0) {
     Some(k) => k.symbol_value().unwrap(),
     None => return Err(MalError::unknown()),
@@ -52,15 +49,14 @@ fn def(env: &mut Env, list: Vec<MalType>) -> MalResult {
     Some(k) => eval(k.to_owned(), env)?,
     None => return Err(MalError::unknown()),
   };
-  env.set(&key, value.clone());
+  env.set(key, value.clone());
   Ok(value)
 }
 
-fn let_star_env(env: &mut Env, list: &mut Vec<MalType>) -> Result<Env, MalError> {
+fn let_star_env(env: &mut Env, list: &MalList) -> Result<Env, MalError> {
   let mut new_env = Env::new(Some(env.to_owned()));
-  let mut list = list.clone();
-  let mut bindings = match list.remove(0) {
-    MalType::List(list) | MalType::Vector(list) => list,
+  let mut bindings = match list.get(0) {
+    Some(MalType::List(list, _, _)) | Some(MalType::Vector(list, _, _)) => list.clone(),
     _ => return Err(MalError::wrong_arguments("Expected list or vector")),
   };
 
@@ -69,32 +65,28 @@ fn let_star_env(env: &mut Env, list: &mut Vec<MalType>) -> Result<Env, MalError>
   }
 
   loop {
-    if bindings.len() == 0 {
+    if bindings.is_empty() {
       break;
     }
-    let symbol = match bindings.remove(0) {
-      MalType::Symbol(sym) => sym,
+    let symbol = match bindings.pop_front().unwrap() {
+      MalType::Symbol(sym, _) => sym,
       _ => return Err(MalError::wrong_arguments("Expected symbol")),
     };
 
-    let value = eval(bindings.remove(0), &mut new_env)?;
-    new_env.set(&symbol, value);
+    let value = eval(bindings.pop_front().unwrap(), &mut new_env)?;
+    new_env.set(symbol, value);
   }
   Ok(new_env)
 }
 
-fn eval_do(input: &mut Vec<MalType>, env: &mut Env) -> MalResult {
-  let list = MalType::List(input[..(input.len() - 1)].to_vec());
-  let _list = eval_ast(list, env)?
-    .list_value()
-    .ok_or(MalError::unknown())?;
-  match input.last() {
-    Some(last) => Ok(last.to_owned()),
-    None => Err(MalError::unknown()),
-  }
+fn eval_do(input: &MalList, env: &mut Env) -> MalResult {
+  let mut list = input.clone();
+  let last = list.pop_back().ok_or(MalError::unknown())?;
+  eval_ast(MalType::List(list, None, None), env)?;
+  Ok(last)
 }
 
-fn eval_if(input: &mut Vec<MalType>, env: &mut Env) -> MalResult {
+fn eval_if(input: &MalList, env: &mut Env) -> MalResult {
   if input.len() < 2 {
     return Err(MalError::unknown());
   }
@@ -111,7 +103,7 @@ fn eval_if(input: &mut Vec<MalType>, env: &mut Env) -> MalResult {
   Ok(result)
 }
 
-fn eval_fn_star(input: &mut Vec<MalType>, env: &mut Env) -> MalResult {
+fn eval_fn_star(input: &MalList, env: &mut Env) -> MalResult {
   if input.len() < 2 {
     return Err(MalError::generic("Not enough args to fn*, expecting 2"));
   }
@@ -122,6 +114,10 @@ fn eval_fn_star(input: &mut Vec<MalType>, env: &mut Env) -> MalResult {
       env: env.clone(),
       args,
       body: vec![body],
+      is_macro: false,
+      meta: Box::new(MalType::Nil),
+      name: None,
+      code: None,
     });
     Ok(lambda)
   } else {
@@ -134,14 +130,14 @@ fn eval_fn_star(input: &mut Vec<MalType>, env: &mut Env) -> MalResult {
 
 fn eval_ast(input: MalType, env: &mut Env) -> MalResult {
   // println!("EVAL AST Input: {}", printer::print_str(&input, true));
-  let mut eval_list = |list: Vec<MalType>| -> Result<Vec<MalType>, MalError> {
-    list.into_iter().map(|v| eval(v.clone(), env)).collect()
+  let mut eval_list = |list: MalList| -> Result<MalList, MalError> {
+    list.into_iter().map(|v| eval(v, env)).collect()
   };
   let value = match input {
-    MalType::Symbol(sym) => env.get(&sym)?,
-    MalType::List(list) => MalType::List(eval_list(list)?),
-    MalType::Vector(list) => MalType::Vector(eval_list(list)?),
-    MalType::HashMap(list) => MalType::HashMap(eval_hash_map(list, env)?),
+    MalType::Symbol(sym, _) => env.get(sym)?,
+    MalType::List(list, _, _) => MalType::List(eval_list(list)?, None, None),
+    MalType::Vector(list, _, _) => MalType::Vector(eval_list(list)?, None, None),
+    MalType::HashMap(map, _) => MalType::HashMap(eval_hash_map(map, env)?, None),
     _ => input,
   };
   Ok(value)
@@ -153,27 +149,27 @@ fn eval(input: MalType, env: &mut Env) -> MalResult {
   loop {
     if !input.is_list() {
       return eval_ast(input, &mut env);
-    } else if input.list_value().unwrap().len() == 0 {
+    } else if input.list_value().unwrap().is_empty() {
       return Ok(input);
     } else if is_special_form(&input) {
       if let Some(mut list) = input.list_value() {
-        if let MalType::Symbol(sym) = list.remove(0) {
-          match sym.as_str() {
-            "def!" => {
+        if let Some(MalType::Symbol(sym, _)) = list.pop_front() {
+          match sym {
+            symbol::DEF => {
               return def(&mut env, list);
             }
-            "let*" => {
-              env = let_star_env(&mut env, &mut list)?;
+            symbol::LET => {
+              env = let_star_env(&mut env, &list)?;
               input = list.get(1).unwrap().clone();
             }
-            "do" => {
-              input = eval_do(&mut list, &mut env)?;
+            symbol::DO => {
+              input = eval_do(&list, &mut env)?;
             }
-            "if" => {
-              input = eval_if(&mut list, &mut env)?;
+            symbol::IF => {
+              input = eval_if(&list, &mut env)?;
             }
-            "fn*" => {
-              return eval_fn_star(&mut list, &mut env);
+            symbol::FN => {
+              return eval_fn_star(&list, &mut env);
             }
             _ => return Err(MalError::generic("Unknown special symbol")),
           };
@@ -187,19 +183,21 @@ fn eval(input: MalType, env: &mut Env) -> MalResult {
       // Must be a function or lambda call
       let mut list = eval_ast(input, &mut env)?.list_value().unwrap();
       match list.remove(0) {
-        MalType::Function(MalFunc { func, env, .. }) => return func(&mut list, env),
+        MalType::Function(func) => return func.call(&mut list.into_iter().collect()),
         MalType::Lambda(MalLambda {
           env: l_env,
           args,
           body,
+          name,
           ..
         }) => {
-          let binds: Vec<String> = args
+          let binds: Vec<Symbol> = args
             .into_iter()
             .filter_map(|val| val.symbol_value())
             .collect();
-          env = Env::new_with_bindings(Some(l_env), binds, list);
-          input = body.get(0).unwrap().clone();
+          let fn_name = name.as_deref().unwrap_or("fn*");
+          env = Env::new_with_bindings(Some(l_env), fn_name, binds, list)?;
+          input = body.first().unwrap().clone();
         }
         _ => return Err(MalError::not_a_function()),
       };
@@ -209,14 +207,14 @@ fn eval(input: MalType, env: &mut Env) -> MalResult {
 
 fn is_special_form(input: &MalType) -> bool {
   if let Some(list) = input.list_value() {
-    if list.len() == 0 {
+    if list.is_empty() {
       return false;
     }
     if let Some(sym) = list[0].symbol_value() {
-      return match sym.as_str() {
-        "def!" | "let*" | "do" | "if" | "fn*" => true,
-        _ => false,
-      };
+      return matches!(
+        sym,
+        symbol::DEF | symbol::LET | symbol::DO | symbol::IF | symbol::FN
+      );
     }
   }
   false
@@ -241,14 +239,8 @@ fn rep(env: &mut Env) -> Result<RepResult, MalError> {
 fn main() {
   let mut env = Env::new(None);
   // Add native lib functions
-  for (sym, func) in &*core::CORE_FUNCTIONS {
-    env.set(
-      sym,
-      MalType::Function(MalFunc {
-        func: *func,
-        env: None,
-      }),
-    )
+  for (sym, def) in &*core::CORE_FUNCTIONS {
+    env.set_native(sym, def.arity, def.func);
   }
   // Eval stdlib mal functions
   let ast = reader::read_str(String::from("(def! not (fn* (a) (if a false true)))")).unwrap();
@@ -1,5 +1,6 @@
 use mal_rust::core;
 use mal_rust::env::Env;
+use mal_rust::symbol;
 use mal_rust::types::*;
 use mal_rust::{printer, reader};
 use rustyline::error::ReadlineError;
@@ -10,36 +11,31 @@ fn read(input: &str) -> MalResult {
   reader::read_str(input.to_string())
 }
 
-fn eval_hash_map(list: Vec<MalType>, env: &mut Env) -> Result<Vec<MalType>, MalError> {
-  let mut new_list = Vec::new();
-  for (i, item) in list.iter().enumerate() {
-    if i % 2 != 0 {
-      new_list.push(eval(item.to_owned(), env)?);
-    } else {
-      new_list.push(item.to_owned());
-    }
+fn eval_hash_map(map: MalHashMap, env: &mut Env) -> Result<MalHashMap, MalError> {
+  let mut new_map = MalHashMap::new();
+  for (key, value) in map {
+    new_map.insert(key, eval(value, env)?);
   }
-  Ok(new_list)
+  Ok(new_map)
 }
 
-fn def(env: &mut Env, list: Vec<MalType>) -> MalResult {
+fn def(env: &mut Env, list: MalList) -> MalResult {
   let key = match list.get(0) {
     Some(k) => k.symbol_value().unwrap(),
     None => return Err(MalError::unknown()),
   };
   let value = match list.get(1) {
-    Some(k) => eval(k.to_owned(), env)?,
+    Some(k) => eval(k.to_owned(), env)?.with_name(&key.name()),
     None => return Err(MalError::unknown()),
   };
-  env.set(&key, value.clone());
+  env.set(key, value.clone());
   Ok(value)
 }
 
-fn let_star_env(env: &mut Env, list: &mut Vec<MalType>) -> Result<Env, MalError> {
+fn let_star_env(env: &mut Env, list: &MalList) -> Result<Env, MalError> {
   let mut new_env = Env::new(Some(env.to_owned()));
-  let mut list = list.clone();
-  let mut bindings = match list.remove(0) {
-    MalType::List(list) | MalType::Vector(list) => list,
+  let mut bindings = match list.get(0) {
+    Some(MalType::List(list, _, _)) | Some(MalType::Vector(list, _, _)) => list.clone(),
     _ => return Err(MalError::wrong_arguments("Expected list or vector")),
   };
 
@@ -48,32 +44,28 @@ fn let_star_env(env: &mut Env, list: &mut Vec<MalType>) -> Result<Env, MalError>
   }
 
   loop {
-    if bindings.len() == 0 {
+    if bindings.is_empty() {
       break;
     }
-    let symbol = match bindings.remove(0) {
-      MalType::Symbol(sym) => sym,
+    let symbol = match bindings.pop_front().unwrap() {
+      MalType::Symbol(sym, _) => sym,
       _ => return Err(MalError::wrong_arguments("Expected symbol")),
     };
 
-    let value = eval(bindings.remove(0), &mut new_env)?;
-    new_env.set(&symbol, value);
+    let value = eval(bindings.pop_front().unwrap(), &mut new_env)?;
+    new_env.set(symbol, value);
   }
   Ok(new_env)
 }
 
-fn eval_do(input: &mut Vec<MalType>, env: &mut Env) -> MalResult {
-  let list = MalType::List(input[..(input.len() - 1)].to_vec());
-  let _list = eval_ast(list, env)?
-    .list_value()
-    .ok_or(MalError::unknown())?;
-  match input.last() {
-    Some(last) => Ok(last.to_owned()),
-    None => Err(MalError::unknown()),
-  }
+fn eval_do(input: &MalList, env: &mut Env) -> MalResult {
+  let mut list = input.clone();
+  let last = list.pop_back().ok_or(MalError::unknown())?;
+  eval_ast(MalType::List(list, None, None), env)?;
+  Ok(last)
 }
 
-fn eval_if(input: &mut Vec<MalType>, env: &mut Env) -> MalResult {
+fn eval_if(input: &MalList, env: &mut Env) -> MalResult {
   if input.len() < 2 {
     return Err(MalError::unknown());
   }
@@ -90,7 +82,7 @@ fn eval_if(input: &mut Vec<MalType>, env: &mut Env) -> MalResult {
   Ok(result)
 }
 
-fn eval_fn_star(input: &mut Vec<MalType>, env: &mut Env) -> MalResult {
+fn eval_fn_star(input: &MalList, env: &mut Env) -> MalResult {
   if input.len() < 2 {
     return Err(MalError::generic("Not enough args to fn*, expecting 2"));
   }
@@ -101,6 +93,10 @@ fn eval_fn_star(input: &mut Vec<MalType>, env: &mut Env) -> MalResult {
       env: env.clone(),
       args,
       body: vec![body],
+      is_macro: false,
+      meta: Box::new(MalType::Nil),
+      name: None,
+      code: None,
     });
     Ok(lambda)
   } else {
@@ -113,46 +109,55 @@ fn eval_fn_star(input: &mut Vec<MalType>, env: &mut Env) -> MalResult {
 
 fn eval_ast(input: MalType, env: &mut Env) -> MalResult {
   // println!("EVAL AST Input: {}", printer::print_str(&input, true));
-  let mut eval_list = |list: Vec<MalType>| -> Result<Vec<MalType>, MalError> {
-    list.into_iter().map(|v| eval(v.clone(), env)).collect()
+  let mut eval_list = |list: MalList| -> Result<MalList, MalError> {
+    list.into_iter().map(|v| eval(v, env)).collect()
   };
   let value = match input {
-    MalType::Symbol(sym) => env.get(&sym)?,
-    MalType::List(list) => MalType::List(eval_list(list)?),
-    MalType::Vector(list) => MalType::Vector(eval_list(list)?),
-    MalType::HashMap(list) => MalType::HashMap(eval_hash_map(list, env)?),
+    MalType::Symbol(sym, _) => env.get(sym)?,
+    MalType::List(list, _, _) => MalType::List(eval_list(list)?, None, None),
+    MalType::Vector(list, _, _) => MalType::Vector(eval_list(list)?, None, None),
+    MalType::HashMap(map, _) => MalType::HashMap(eval_hash_map(map, env)?, None),
     _ => input,
   };
   Ok(value)
 }
 
 fn eval(input: MalType, env: &mut Env) -> MalResult {
+  let mut frame = None;
+  eval_in_frame(input, env, &mut frame).map_err(|err| match frame {
+    Some(frame) => err.with_frame(frame),
+    None => err,
+  })
+}
+
+// Evaluates with tail calls, recording the lambda currently running in `frame`
+fn eval_in_frame(input: MalType, env: &mut Env, frame: &mut Option<Frame>) -> MalResult {
   let mut input = input.clone();
   let mut env = env.clone();
   loop {
     if !input.is_list() {
       return eval_ast(input, &mut env);
-    } else if input.list_value().unwrap().len() == 0 {
+    } else if input.list_value().unwrap().is_empty() {
       return Ok(input);
     } else if is_special_form(&input) {
       if let Some(mut list) = input.list_value() {
-        if let MalType::Symbol(sym) = list.remove(0) {
-          match sym.as_str() {
-            "def!" => {
+        if let Some(MalType::Symbol(sym, _)) = list.pop_front() {
+          match sym {
+            symbol::DEF => {
               return def(&mut env, list);
             }
-            "let*" => {
-              env = let_star_env(&mut env, &mut list)?;
+            symbol::LET => {
+              env = let_star_env(&mut env, &list)?;
               input = list.get(1).unwrap().clone();
             }
-            "do" => {
-              input = eval_do(&mut list, &mut env)?;
+            symbol::DO => {
+              input = eval_do(&list, &mut env)?;
             }
-            "if" => {
-              input = eval_if(&mut list, &mut env)?;
+            symbol::IF => {
+              input = eval_if(&list, &mut env)?;
             }
-            "fn*" => {
-              return eval_fn_star(&mut list, &mut env);
+            symbol::FN => {
+              return eval_fn_star(&list, &mut env);
             }
             _ => return Err(MalError::generic("Unknown special symbol")),
           };
@@ -164,23 +169,35 @@ fn eval(input: MalType, env: &mut Env) -> MalResult {
       }
     } else {
       // Must be a function or lambda call
-      let mut list = eval_ast(input, &mut env)?.list_value().unwrap();
-      match list.remove(0) {
-        MalType::Function(MalFunc { func, env, .. }) => return func(&mut list, env),
+      let span = input.span();
+      let mut list = eval_ast(input, &mut env)
+        .map_err(|err| err.with_span(span.clone()))?
+        .list_value()
+        .unwrap();
+      match list.pop_front().unwrap() {
+        MalType::Function(func) => {
+          let mut args = list.into_iter().collect();
+          return func.call(&mut args).map_err(|err| err.with_span(span));
+        }
         MalType::Lambda(MalLambda {
           env: l_env,
           args,
           body,
+          name,
           ..
         }) => {
-          let binds: Vec<String> = args
+          let binds: Vec<Symbol> = args
             .into_iter()
             .filter_map(|val| val.symbol_value())
             .collect();
-          env = Env::new_with_bindings(Some(l_env), binds, list);
-          input = body.get(0).unwrap().clone();
+          let fn_name = name.as_deref().unwrap_or("fn*");
+          env = Env::new_with_bindings(Some(l_env), fn_name, binds, list)
+            .map_err(|err| err.with_span(span.clone()))?;
+          input = body.first().unwrap().clone();
+          // A tail call replaces the caller's frame
+          *frame = Some(Frame { name, span });
         }
-        _ => return Err(MalError::not_a_function()),
+        _ => return Err(MalError::not_a_function().with_span(span)),
       };
     }
   }
@@ -188,23 +205,23 @@ fn eval(input: MalType, env: &mut Env) -> MalResult {
 
 fn is_special_form(input: &MalType) -> bool {
   if let Some(list) = input.list_value() {
-    if list.len() == 0 {
+    if list.is_empty() {
       return false;
     }
     if let Some(sym) = list[0].symbol_value() {
-      return match sym.as_str() {
-        "def!" | "let*" | "do" | "if" | "fn*" => true,
-        _ => false,
-      };
+      return matches!(
+        sym,
+        symbol::DEF | symbol::LET | symbol::DO | symbol::IF | symbol::FN
+      );
     }
   }
   false
 }
 
-fn eval_fn(args: &mut Vec<MalType>, env: Option<Env>) -> MalResult {
-  if let Some(arg) = args.get(0) {
-    let mut env = env.expect("No env provided");
-    eval(arg.to_owned(), &mut env)
+#[allow(clippy::ptr_arg)]
+fn eval_fn(args: &mut Vec<MalType>, env: &Env) -> MalResult {
+  if let Some(arg) = args.first() {
+    eval(arg.to_owned(), &mut env.clone())
   } else {
     Err(MalError::generic("Not enough arguments"))
   }
@@ -223,39 +240,23 @@ fn rep(input: String, env: &mut Env) -> Result<String, MalError> {
 fn main() {
   let mut env = Env::new(None);
   // Add native lib functions
-  for (sym, func) in &*core::CORE_FUNCTIONS {
-    env.set(
-      sym,
-      MalType::Function(MalFunc {
-        func: *func,
-        env: None,
-      }),
-    )
+  for (sym, def) in &*core::CORE_FUNCTIONS {
+    env.set_native(sym, def.arity, def.func);
   }
-  env.set(
-    "eval",
-    MalType::Function(MalFunc {
-      func: eval_fn,
-      env: Some(env.clone()),
-    }),
-  );
+  let global = env.clone();
+  env.set_native("eval", Arity::exact(1), move |args| eval_fn(args, &global));
 
-  env.set("*ARGV*", MalType::List(vec![]));
+  env.set("*ARGV*", MalType::List(MalList::new(), None, None));
   // Eval stdlib mal functions
   let ast = reader::read_str(String::from("(def! not (fn* (a) (if a false true)))")).unwrap();
   eval(ast, &mut env).unwrap();
 
-  let ast = reader::read_str(
-    "(def! load-file (fn* (f) (eval (read-string (str \"(do \" (slurp f) \"\nnil)\")))))"
-      .to_string(),
-  )
-  .unwrap();
+  let ast =
+    reader::read_str("(def! load-file (fn* (f) (eval (read-file f))))".to_string()).unwrap();
   eval(ast, &mut env).unwrap();
 
   let mut rl = Editor::<()>::new();
-  match rl.load_history(".mal-history") {
-    _ => {}
-  }
+  let _ = rl.load_history(".mal-history");
 
   let mut args: Vec<String> = env::args().collect();
   if args.len() > 1 {
@@ -263,7 +264,11 @@ fn main() {
     let file = args.remove(0);
     env.set(
       "*ARGV*",
-      MalType::List(args.iter().map(|it| MalType::String(it.clone())).collect()),
+      MalType::List(
+        args.iter().map(|it| MalType::String(it.clone())).collect(),
+        None,
+        None,
+      ),
     );
     let result = rep(format!("(load-file \"{}\")", file), &mut env);
     match result {
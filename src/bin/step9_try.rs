@@ -0,0 +1,478 @@
+use im_rc::vector;
+use mal_rust::core;
+use mal_rust::env::Env;
+use mal_rust::symbol;
+use mal_rust::types::*;
+use mal_rust::{printer, reader};
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
+use std::{env, process};
+
+fn read(input: &str) -> MalResult {
+  reader::read_str(input.to_string())
+}
+
+fn eval_hash_map(map: MalHashMap, env: &mut Env) -> Result<MalHashMap, MalError> {
+  let mut new_map = MalHashMap::new();
+  for (key, value) in map {
+    new_map.insert(key, eval(value, env)?);
+  }
+  Ok(new_map)
+}
+
+fn def(env: &mut Env, list: MalList) -> MalResult {
+  let key = match list.get(0) {
+    Some(k) => k.symbol_value().unwrap(),
+    None => return Err(MalError::unknown()),
+  };
+  let value = match list.get(1) {
+    Some(k) => eval(k.to_owned(), env)?.with_name(&key.name()),
+    None => return Err(MalError::unknown()),
+  };
+  env.set(key, value.clone());
+  Ok(value)
+}
+
+fn defmacro(env: &mut Env, list: MalList) -> MalResult {
+  let key = match list.get(0) {
+    Some(k) => k.symbol_value().unwrap(),
+    None => return Err(MalError::unknown()),
+  };
+  let value = match list.get(1) {
+    Some(k) => eval(k.to_owned(), env)?.with_name(&key.name()),
+    None => return Err(MalError::unknown()),
+  };
+  match value {
+    MalType::Lambda(lambda) => {
+      let value = MalType::Lambda(MalLambda {
+        is_macro: true,
+        ..lambda
+      });
+      env.set(key, value.clone());
+      Ok(value)
+    }
+    _ => Err(MalError::wrong_arguments("Expected fn* as macro body")),
+  }
+}
+
+fn let_star_env(env: &mut Env, list: &MalList) -> Result<Env, MalError> {
+  let mut new_env = Env::new(Some(env.to_owned()));
+  let mut bindings = match list.get(0) {
+    Some(MalType::List(list, _, _)) | Some(MalType::Vector(list, _, _)) => list.clone(),
+    _ => return Err(MalError::wrong_arguments("Expected list or vector")),
+  };
+
+  if bindings.len() % 2 != 0 {
+    return Err(MalError::unknown());
+  }
+
+  loop {
+    if bindings.is_empty() {
+      break;
+    }
+    let symbol = match bindings.pop_front().unwrap() {
+      MalType::Symbol(sym, _) => sym,
+      _ => return Err(MalError::wrong_arguments("Expected symbol")),
+    };
+
+    let value = eval(bindings.pop_front().unwrap(), &mut new_env)?;
+    new_env.set(symbol, value);
+  }
+  Ok(new_env)
+}
+
+fn eval_do(input: &MalList, env: &mut Env) -> MalResult {
+  let mut list = input.clone();
+  let last = list.pop_back().ok_or(MalError::unknown())?;
+  eval_ast(MalType::List(list, None, None), env)?;
+  Ok(last)
+}
+
+fn eval_if(input: &MalList, env: &mut Env) -> MalResult {
+  if input.len() < 2 {
+    return Err(MalError::unknown());
+  }
+
+  let condition = eval(input[0].to_owned(), env)?;
+  let result = if condition.is_truthy() {
+    input[1].to_owned()
+  } else {
+    match input.get(2) {
+      Some(falsey) => falsey.to_owned(),
+      None => MalType::Nil,
+    }
+  };
+  Ok(result)
+}
+
+fn eval_fn_star(input: &MalList, env: &mut Env) -> MalResult {
+  if input.len() < 2 {
+    return Err(MalError::generic("Not enough args to fn*, expecting 2"));
+  }
+  let args = &input[0];
+  if let Some(args) = args.list_value() {
+    let body = input[1].clone();
+    let lambda = MalType::Lambda(MalLambda {
+      env: env.clone(),
+      args,
+      body: vec![body],
+      is_macro: false,
+      meta: Box::new(MalType::Nil),
+      name: None,
+      code: None,
+    });
+    Ok(lambda)
+  } else {
+    Err(MalError::generic(&format!(
+      "Expecting vector as first argument of fn*, but got: {:?}",
+      args
+    )))
+  }
+}
+
+fn eval_try(input: &MalList, env: &mut Env) -> MalResult {
+  let body = match input.get(0) {
+    Some(body) => body.to_owned(),
+    None => return Ok(MalType::Nil),
+  };
+  let result = eval(body, env);
+  let catch = match input.get(1) {
+    Some(catch) => catch.list_value().unwrap_or_default(),
+    None => return result,
+  };
+  if catch.len() < 3 || !catch[0].is_symbol_named(symbol::CATCH) {
+    return Err(MalError::wrong_arguments(
+      "Expected (catch* symbol body) as second argument of try*",
+    ));
+  }
+  match result {
+    Ok(value) => Ok(value),
+    Err(err) => {
+      let binding = match catch[1].symbol_value() {
+        Some(sym) => sym,
+        None => return Err(MalError::wrong_arguments("Expected symbol in catch*")),
+      };
+      let mut catch_env = Env::new_with_bindings(
+        Some(env.clone()),
+        "catch*",
+        vec![binding],
+        vector![err.exception_value()],
+      )?;
+      catch_env.set("*backtrace*", err.backtrace_value());
+      eval(catch[2].to_owned(), &mut catch_env)
+    }
+  }
+}
+
+fn eval_ast(input: MalType, env: &mut Env) -> MalResult {
+  let mut eval_list = |list: MalList| -> Result<MalList, MalError> {
+    list.into_iter().map(|v| eval(v, env)).collect()
+  };
+  let value = match input {
+    MalType::Symbol(sym, _) => env.get(sym)?,
+    MalType::List(list, _, _) => MalType::List(eval_list(list)?, None, None),
+    MalType::Vector(list, _, _) => MalType::Vector(eval_list(list)?, None, None),
+    MalType::HashMap(map, _) => MalType::HashMap(eval_hash_map(map, env)?, None),
+    _ => input,
+  };
+  Ok(value)
+}
+
+fn eval(input: MalType, env: &mut Env) -> MalResult {
+  let mut frame = None;
+  eval_in_frame(input, env, &mut frame).map_err(|err| match frame {
+    Some(frame) => err.with_frame(frame),
+    None => err,
+  })
+}
+
+// Evaluates with tail calls, recording the lambda currently running in `frame`
+fn eval_in_frame(input: MalType, env: &mut Env, frame: &mut Option<Frame>) -> MalResult {
+  let mut input = input.clone();
+  let mut env = env.clone();
+  loop {
+    if !input.is_list() {
+      return eval_ast(input, &mut env);
+    }
+    input = macroexpand(input, &mut env)?;
+    if !input.is_list() {
+      return eval_ast(input, &mut env);
+    } else if input.list_value().unwrap().is_empty() {
+      return Ok(input);
+    } else if is_special_form(&input) {
+      if let Some(mut list) = input.list_value() {
+        if let Some(MalType::Symbol(sym, _)) = list.pop_front() {
+          match sym {
+            symbol::DEF => {
+              return def(&mut env, list);
+            }
+            symbol::LET => {
+              env = let_star_env(&mut env, &list)?;
+              input = list.get(1).unwrap().clone();
+            }
+            symbol::DO => {
+              input = eval_do(&list, &mut env)?;
+            }
+            symbol::IF => {
+              input = eval_if(&list, &mut env)?;
+            }
+            symbol::FN => {
+              return eval_fn_star(&list, &mut env);
+            }
+            symbol::QUOTE => {
+              if list.is_empty() {
+                return Err(MalError::wrong_arguments("Missing argument for quote"));
+              }
+              return Ok(list.pop_front().unwrap());
+            }
+            symbol::QUASIQUOTE => {
+              input = match list.pop_front() {
+                Some(ast) => quasiquote(ast),
+                None => MalType::List(MalList::new(), None, None),
+              };
+            }
+            symbol::DEFMACRO => {
+              return defmacro(&mut env, list);
+            }
+            symbol::TRY => {
+              return eval_try(&list, &mut env);
+            }
+            symbol::MACROEXPAND => {
+              if list.is_empty() {
+                return Err(MalError::wrong_arguments(
+                  "Missing argument for macroexpand",
+                ));
+              }
+              return macroexpand(list.pop_front().unwrap(), &mut env);
+            }
+            _ => return Err(MalError::generic("Unknown special symbol")),
+          };
+        } else {
+          panic!("No longer a list somehow");
+        }
+      } else {
+        panic!("No longer a list somehow");
+      }
+    } else {
+      // Must be a function or lambda call
+      let span = input.span();
+      let mut list = eval_ast(input, &mut env)
+        .map_err(|err| err.with_span(span.clone()))?
+        .list_value()
+        .unwrap();
+      match list.pop_front().unwrap() {
+        MalType::Function(func) => {
+          let mut args = list.into_iter().collect();
+          return func.call(&mut args).map_err(|err| err.with_span(span));
+        }
+        MalType::Lambda(MalLambda {
+          env: l_env,
+          args,
+          body,
+          name,
+          ..
+        }) => {
+          let binds: Vec<Symbol> = args
+            .into_iter()
+            .filter_map(|val| val.symbol_value())
+            .collect();
+          let fn_name = name.as_deref().unwrap_or("fn*");
+          env = Env::new_with_bindings(Some(l_env), fn_name, binds, list)
+            .map_err(|err| err.with_span(span.clone()))?;
+          input = body.first().unwrap().clone();
+          // A tail call replaces the caller's frame
+          *frame = Some(Frame { name, span });
+        }
+        _ => return Err(MalError::not_a_function().with_span(span)),
+      };
+    }
+  }
+}
+
+fn is_macro_call(input: &MalType, env: &Env) -> bool {
+  if let Some(list) = input.list_value() {
+    if let Some(MalType::Symbol(sym, _)) = list.front() {
+      if let Ok(value) = env.get(*sym) {
+        return value.is_macro();
+      }
+    }
+  }
+  false
+}
+
+fn macroexpand(input: MalType, env: &mut Env) -> MalResult {
+  let mut input = input;
+  while is_macro_call(&input, env) {
+    let mut list = input.list_value().unwrap();
+    let sym = list.pop_front().unwrap().symbol_value().unwrap();
+    let MalLambda {
+      env: l_env,
+      args,
+      body,
+      ..
+    } = env.get(sym)?.lambda_value().unwrap();
+    let binds: Vec<Symbol> = args
+      .into_iter()
+      .filter_map(|val| val.symbol_value())
+      .collect();
+    let mut macro_env = Env::new_with_bindings(Some(l_env), &sym.name(), binds, list)?;
+    input = eval(body.first().unwrap().clone(), &mut macro_env)?;
+  }
+  Ok(input)
+}
+
+fn quasiquote(ast: MalType) -> MalType {
+  if !ast.is_pair() {
+    MalType::List(
+      vector![MalType::Symbol(symbol::QUOTE, None), ast],
+      None,
+      None,
+    )
+  } else if first(&ast).is_symbol_named(symbol::UNQUOTE) {
+    first(&rest(&ast))
+  } else if first(&ast).is_pair() && first(&first(&ast)).is_symbol_named(symbol::SPLICE_UNQUOTE) {
+    let ret_list = vector![
+      MalType::Symbol(symbol::CONCAT, None),
+      first(&rest(&first(&ast))),
+      quasiquote(rest(&ast)),
+    ];
+    MalType::List(ret_list, None, None)
+  } else {
+    let ret_list = vector![
+      MalType::Symbol(symbol::CONS, None),
+      quasiquote(first(&ast)),
+      quasiquote(rest(&ast)),
+    ];
+    MalType::List(ret_list, None, None)
+  }
+}
+
+fn first(val: &MalType) -> MalType {
+  let list = val.list_value().expect("Requires a list");
+  list.front().expect("Requires a non-empty list").clone()
+}
+
+fn rest(val: &MalType) -> MalType {
+  let list = val.list_value().expect("Requires a list");
+  assert!(!list.is_empty());
+  MalType::List(list.skip(1), None, None)
+}
+
+fn is_special_form(input: &MalType) -> bool {
+  if let Some(list) = input.list_value() {
+    if list.is_empty() {
+      return false;
+    }
+    if let Some(sym) = list[0].symbol_value() {
+      return matches!(
+        sym,
+        symbol::DEF
+          | symbol::LET
+          | symbol::DO
+          | symbol::IF
+          | symbol::FN
+          | symbol::QUOTE
+          | symbol::QUASIQUOTE
+          | symbol::DEFMACRO
+          | symbol::MACROEXPAND
+          | symbol::TRY
+      );
+    }
+  }
+  false
+}
+
+#[allow(clippy::ptr_arg)]
+fn eval_fn(args: &mut Vec<MalType>, env: &Env) -> MalResult {
+  if let Some(arg) = args.first() {
+    eval(arg.to_owned(), &mut env.clone())
+  } else {
+    Err(MalError::generic("Not enough arguments"))
+  }
+}
+
+fn print(output: MalType) -> String {
+  printer::print_str(&output, true)
+}
+
+fn rep(input: String, env: &mut Env) -> Result<String, MalError> {
+  let out = read(&input)?;
+  let out = print(eval(out, env)?);
+  Ok(out)
+}
+
+fn main() {
+  let mut env = Env::new(None);
+  // Add native lib functions
+  for (sym, def) in &*core::CORE_FUNCTIONS {
+    env.set_native(sym, def.arity, def.func);
+  }
+  let global = env.clone();
+  env.set_native("eval", Arity::exact(1), move |args| eval_fn(args, &global));
+
+  env.set("*ARGV*", MalType::List(MalList::new(), None, None));
+  // Eval stdlib mal functions
+  let ast = reader::read_str(String::from("(def! not (fn* (a) (if a false true)))")).unwrap();
+  eval(ast, &mut env).unwrap();
+
+  let ast =
+    reader::read_str("(def! load-file (fn* (f) (eval (read-file f))))".to_string()).unwrap();
+  eval(ast, &mut env).unwrap();
+
+  let ast = reader::read_str(
+    "(defmacro! cond (fn* (& xs) (if (> (count xs) 0) (list 'if (first xs) (if (> (count xs) 1) (nth xs 1) (throw \"odd number of forms to cond\")) (cons 'cond (rest (rest xs)))))))"
+      .to_string(),
+  )
+  .unwrap();
+  eval(ast, &mut env).unwrap();
+
+  let mut rl = Editor::<()>::new();
+  let _ = rl.load_history(".mal-history");
+
+  let mut args: Vec<String> = env::args().collect();
+  if args.len() > 1 {
+    args.remove(0); // Remove name of executable
+    let file = args.remove(0);
+    env.set(
+      "*ARGV*",
+      MalType::List(
+        args.iter().map(|it| MalType::String(it.clone())).collect(),
+        None,
+        None,
+      ),
+    );
+    let result = rep(format!("(load-file \"{}\")", file), &mut env);
+    match result {
+      Err(err) => {
+        eprintln!("{}", err);
+        process::exit(1);
+      }
+      _ => process::exit(0),
+    }
+  }
+
+  loop {
+    let readline = rl.readline("user> ");
+    match readline {
+      Ok(line) => {
+        rl.add_history_entry(line.as_str());
+        match rep(line, &mut env) {
+          Ok(out) => println!("{}", out),
+          Err(err) => match err.reason() {
+            MalErrorReason::BlankLine => (),
+            _ => eprintln!("{}", err),
+          },
+        }
+      }
+      Err(ReadlineError::Interrupted) => {
+        // Do nothing
+      }
+      Err(ReadlineError::Eof) => break,
+      Err(err) => {
+        println!("Error: {:?}", err);
+        break;
+      }
+    }
+  }
+  rl.save_history(".mal-history").unwrap();
+}
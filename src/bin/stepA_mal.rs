@@ -0,0 +1,111 @@
+use mal_rust::interpreter::{Engine, Interpreter};
+use mal_rust::limits::{self, Limits};
+use mal_rust::printer;
+use mal_rust::types::*;
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
+use std::panic::{self, AssertUnwindSafe};
+use std::{env, process};
+
+fn print(output: MalType) -> String {
+  printer::print_str(&output, true)
+}
+
+fn rep(input: String, interpreter: &mut Interpreter) -> Result<String, MalError> {
+  let out = interpreter.eval_str(&input)?;
+  Ok(print(out))
+}
+
+// Runs an evaluation, turning a panic in the host into an error so the session survives it
+fn guarded<F>(run: F) -> MalResult
+where
+  F: FnOnce() -> MalResult,
+{
+  match panic::catch_unwind(AssertUnwindSafe(run)) {
+    Ok(result) => result,
+    Err(payload) => {
+      let msg = if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+      } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.to_owned()
+      } else {
+        "unknown panic".to_string()
+      };
+      Err(MalError::generic(&format!("Internal error - {}", msg)))
+    }
+  }
+}
+
+fn main() {
+  // Deep recursion needs more stack than the main thread has
+  limits::with_stack(limits::STACK_SIZE, run);
+}
+
+fn run() {
+  // Panics are reported through guarded instead of the default hook
+  panic::set_hook(Box::new(|_| {}));
+
+  // MAL_ENGINE=vm runs forms on the bytecode VM instead of the tree-walker
+  let engine = match env::var("MAL_ENGINE").as_deref() {
+    Ok("vm") => Engine::Vm,
+    _ => Engine::TreeWalker,
+  };
+  let mut interpreter = Interpreter::with_engine(engine);
+  interpreter.set_limits(Limits::with_stack_size(limits::STACK_SIZE));
+
+  let mut rl = Editor::<()>::new();
+  let _ = rl.load_history(".mal-history");
+
+  let mut args: Vec<String> = env::args().collect();
+  if args.len() > 1 {
+    args.remove(0); // Remove name of executable
+    let file = args.remove(0);
+    interpreter.global_env().set(
+      "*ARGV*",
+      MalType::List(
+        args.iter().map(|it| MalType::String(it.clone())).collect(),
+        None,
+        None,
+      ),
+    );
+    let result = guarded(|| interpreter.load_file(&file));
+    match result {
+      Err(err) => {
+        eprintln!("{}", err);
+        process::exit(1);
+      }
+      _ => process::exit(0),
+    }
+  }
+
+  rep(
+    "(println (str \"Mal [\" *host-language* \"]\"))".to_string(),
+    &mut interpreter,
+  )
+  .unwrap();
+
+  loop {
+    let readline = rl.readline("user> ");
+    match readline {
+      Ok(line) => {
+        rl.add_history_entry(line.as_str());
+        match guarded(|| interpreter.eval_str(&line)) {
+          Ok(out) => println!("{}", print(out)),
+          Err(err) => match err.reason() {
+            MalErrorReason::BlankLine => (),
+            _ => eprintln!("{}", err),
+          },
+        }
+      }
+      Err(ReadlineError::Interrupted) => {
+        // Do nothing
+      }
+      Err(ReadlineError::Eof) => break,
+      Err(err) => {
+        println!("Error: {:?}", err);
+        break;
+      }
+    }
+  }
+  rl.save_history(".mal-history").unwrap();
+}
@@ -0,0 +1,60 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::core::{Access, CoreDef, CORE_FUNCTIONS};
+use crate::env::Env;
+use crate::types::*;
+
+// Which core functions reaching outside the interpreter a global environment
+// gets. Printing with prn and println is allowed under every profile.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Capabilities {
+  // No files, terminal or clock
+  Pure,
+  // Reading files inside a directory. Paths are relative to it, and may not
+  // leave it through `..` or symlinks.
+  ReadOnly(PathBuf),
+  // Every core function
+  Full,
+}
+
+impl Capabilities {
+  // Sets the core functions these capabilities allow on `env`
+  pub fn install(&self, env: &mut Env) {
+    for (sym, def) in &*CORE_FUNCTIONS {
+      match (def.access, self) {
+        (None, _) | (_, Capabilities::Full) => env.set_native(sym, def.arity, def.func),
+        (Some(Access::Files), Capabilities::ReadOnly(root)) => {
+          let root = root.clone();
+          env.set_native(sym, def.arity, move |args| rooted(def, &root, args))
+        }
+        _ => {}
+      }
+    }
+  }
+}
+
+// Calls a file function with its path resolved inside `root`
+fn rooted(def: &CoreDef, root: &Path, args: &mut Vec<MalType>) -> MalResult {
+  let path = match &args[0] {
+    MalType::String(path) => resolve(root, path)?,
+    value => return Err(MalError::wrong_type("string", value)),
+  };
+  args[0] = MalType::String(path.to_string_lossy().into_owned());
+  (def.func)(args)
+}
+
+// Resolves `path` against `root` once symlinks and `..` are followed, failing
+// if it ends up outside
+fn resolve(root: &Path, path: &str) -> Result<PathBuf, MalError> {
+  let io_error = |err: std::io::Error| MalError::generic(&err.to_string());
+  let root = fs::canonicalize(root).map_err(io_error)?;
+  let resolved = fs::canonicalize(root.join(path)).map_err(io_error)?;
+  if !resolved.starts_with(&root) {
+    return Err(MalError::generic(&format!(
+      "Access denied - '{}' is outside the allowed directory",
+      path
+    )));
+  }
+  Ok(resolved)
+}
@@ -0,0 +1,423 @@
+use std::rc::Rc;
+
+use crate::analyzer::{self, Scopes};
+use crate::core;
+use crate::env::Env;
+use crate::interpreter;
+use crate::symbol;
+use crate::types::*;
+
+// Instructions for the VM. Operands index the chunk's tables, except for
+// counts, jump targets and the symbols of globals.
+#[derive(Debug, Clone, Copy)]
+pub enum Op {
+  Const(usize),
+  GetLocal(usize, usize),
+  GetGlobal(Symbol),
+  // Binds the value on top of the stack in the current env, leaving it there
+  Def(Symbol),
+  DefMacro(Symbol),
+  // Enters a let* frame for the names at this index of `frames`
+  PushFrame(usize),
+  // Moves the value on top of the stack into the next slot of the current frame
+  SetSlot,
+  PopFrame,
+  Pop,
+  Jump(usize),
+  JumpIfFalse(usize),
+  Call(usize),
+  TailCall(usize),
+  Return,
+  // Creates a lambda closing over the current env from this index of `lambdas`
+  Closure(usize),
+  Vector(usize),
+  // Builds a hash-map whose keys are those of the constant at this index,
+  // taking their values from the stack in the order the constant iterates them
+  HashMap(usize),
+  // Catches errors raised until the matching EndTry by binding them to the
+  // symbol in a new frame and jumping to the handler
+  Try(usize, Symbol),
+  EndTry,
+  // Raises the error at this index of `errors`
+  Fail(usize),
+  // Follows the head of a call that wasn't a macro when it was compiled. If
+  // it is one now, expands the call form at this index of `constants` and
+  // runs the expansion in place of the call, which ends at the target.
+  ExpandMacro(usize, usize),
+}
+
+// Compiled code for a top-level form or lambda body
+#[derive(Debug, Default)]
+pub struct Chunk {
+  pub code: Vec<Op>,
+  // Where each instruction came from, for reporting errors
+  pub spans: Vec<Option<Rc<Span>>>,
+  pub constants: Vec<MalType>,
+  pub frames: Vec<Vec<Symbol>>,
+  pub lambdas: Vec<Proto>,
+  pub errors: Vec<MalError>,
+}
+
+impl Chunk {
+  // Code returning the value on top of the stack
+  pub fn returning() -> Chunk {
+    Chunk {
+      code: vec![Op::Return],
+      spans: vec![None],
+      ..Chunk::default()
+    }
+  }
+}
+
+// A fn* form, from which Op::Closure creates lambdas
+#[derive(Debug)]
+pub struct Proto {
+  pub params: MalList,
+  pub body: MalType,
+  pub code: Rc<Chunk>,
+}
+
+// Compiles `form` to run in `env`. Macros are expanded against `env` as they
+// are reached, and calls of macros defined later are expanded when they run.
+pub fn compile(form: MalType, env: &Env) -> Rc<Chunk> {
+  let mut scopes = Scopes::new(&form, env);
+  let mut compiler = Compiler {
+    chunk: Chunk::default(),
+    scopes: &mut scopes,
+    span: None,
+  };
+  compiler.compile_guarded(form, true);
+  compiler.emit(Op::Return);
+  Rc::new(compiler.chunk)
+}
+
+struct Compiler<'s, 'a> {
+  chunk: Chunk,
+  scopes: &'s mut Scopes<'a>,
+  // Span of the innermost form being compiled that has one
+  span: Option<Rc<Span>>,
+}
+
+impl<'s, 'a> Compiler<'s, 'a> {
+  fn emit(&mut self, op: Op) -> usize {
+    self.chunk.code.push(op);
+    self.chunk.spans.push(self.span.clone());
+    self.chunk.code.len() - 1
+  }
+
+  fn constant(&mut self, value: MalType) -> usize {
+    self.chunk.constants.push(value);
+    self.emit(Op::Const(self.chunk.constants.len() - 1))
+  }
+
+  // Points the jump at `at` to the next instruction
+  fn patch(&mut self, at: usize) {
+    let target = self.chunk.code.len();
+    self.chunk.code[at] = match self.chunk.code[at] {
+      Op::Jump(_) => Op::Jump(target),
+      Op::JumpIfFalse(_) => Op::JumpIfFalse(target),
+      Op::Try(_, name) => Op::Try(target, name),
+      Op::ExpandMacro(index, _) => Op::ExpandMacro(index, target),
+      op => op,
+    };
+  }
+
+  // Compiles `form`, or code raising the error compiling it failed with, so
+  // malformed forms and failing macros only raise once they are evaluated
+  fn compile_guarded(&mut self, form: MalType, tail: bool) {
+    let start = self.chunk.code.len();
+    let depth = self.scopes.depth();
+    if let Err(err) = self.compile_form(form, tail) {
+      self.chunk.code.truncate(start);
+      self.chunk.spans.truncate(start);
+      self.scopes.truncate(depth);
+      self.chunk.errors.push(err);
+      self.emit(Op::Fail(self.chunk.errors.len() - 1));
+    }
+  }
+
+  fn compile_form(&mut self, form: MalType, tail: bool) -> Result<(), MalError> {
+    match form {
+      MalType::Symbol(sym, _) | MalType::Local(sym, _, _) => {
+        let outer = self.enter_span(form.span());
+        match self.scopes.resolve(sym) {
+          Some(MalType::Local(_, depth, slot)) => self.emit(Op::GetLocal(depth, slot)),
+          _ => self.emit(Op::GetGlobal(sym)),
+        };
+        self.span = outer;
+      }
+      MalType::List(list, span, _) if !list.is_empty() => {
+        let outer = std::mem::replace(&mut self.span, span.clone());
+        let result = self
+          .compile_list(list, span.clone(), tail)
+          .map_err(|err| err.with_span(span));
+        self.span = outer;
+        result?;
+      }
+      MalType::Vector(list, span, _) => {
+        let outer = self.enter_span(span);
+        let count = list.len();
+        let result = self
+          .compile_all(list)
+          .map(|()| self.emit(Op::Vector(count)));
+        self.span = outer;
+        result?;
+      }
+      MalType::HashMap(map, _) => {
+        let values: MalList = map.values().cloned().collect();
+        self.compile_all(values)?;
+        self.chunk.constants.push(MalType::HashMap(map, None));
+        self.emit(Op::HashMap(self.chunk.constants.len() - 1));
+      }
+      _ => {
+        self.constant(form);
+      }
+    }
+    Ok(())
+  }
+
+  // Makes `span`, if there is one, the span of the instructions emitted next,
+  // returning the span to put back afterwards
+  fn enter_span(&mut self, span: Option<Rc<Span>>) -> Option<Rc<Span>> {
+    match span {
+      Some(span) => self.span.replace(span),
+      None => self.span.clone(),
+    }
+  }
+
+  fn compile_all(&mut self, list: MalList) -> Result<(), MalError> {
+    for form in list {
+      self.compile_form(form, false)?;
+    }
+    Ok(())
+  }
+
+  fn compile_list(
+    &mut self,
+    mut list: MalList,
+    span: Option<Rc<Span>>,
+    tail: bool,
+  ) -> Result<(), MalError> {
+    let head = match list.front() {
+      Some(MalType::Symbol(sym, _)) => Some(*sym),
+      _ => None,
+    };
+    match head {
+      Some(symbol::DEF) | Some(symbol::DEFMACRO) => {
+        let name = match (list.get(1), list.get(2)) {
+          (Some(name), Some(_)) => name
+            .symbol_value()
+            .ok_or_else(|| MalError::wrong_arguments("Expected symbol"))?,
+          _ => return Err(MalError::unknown()),
+        };
+        self.compile_form(list[2].clone(), false)?;
+        if head == Some(symbol::DEF) {
+          self.emit(Op::Def(name));
+        } else {
+          self.emit(Op::DefMacro(name));
+        }
+      }
+      Some(symbol::LET) => self.compile_let(list, tail)?,
+      Some(symbol::DO) => {
+        list.pop_front();
+        let last = list.pop_back().ok_or_else(MalError::unknown)?;
+        for form in list {
+          self.compile_form(form, false)?;
+          self.emit(Op::Pop);
+        }
+        self.compile_form(last, tail)?;
+      }
+      Some(symbol::IF) => {
+        if list.len() < 3 {
+          return Err(MalError::unknown());
+        }
+        self.compile_form(list[1].clone(), false)?;
+        let to_else = self.emit(Op::JumpIfFalse(0));
+        self.compile_form(list[2].clone(), tail)?;
+        let to_end = self.emit(Op::Jump(0));
+        self.patch(to_else);
+        match list.get(3) {
+          Some(falsey) => self.compile_form(falsey.clone(), tail)?,
+          None => {
+            self.constant(MalType::Nil);
+          }
+        }
+        self.patch(to_end);
+      }
+      Some(symbol::FN) | Some(symbol::FN_ANALYZED) => self.compile_fn(list)?,
+      Some(symbol::QUOTE) => {
+        let value = list
+          .get(1)
+          .cloned()
+          .ok_or_else(|| MalError::wrong_arguments("Missing argument for quote"))?;
+        self.constant(value);
+      }
+      Some(symbol::QUASIQUOTE) => {
+        let expansion = match list.get(1) {
+          Some(ast) => interpreter::quasiquote(ast.clone())?,
+          None => MalType::List(MalList::new(), None, None),
+        };
+        self.compile_form(expansion, tail)?;
+      }
+      Some(symbol::MACROEXPAND) => {
+        let form = list
+          .get(1)
+          .cloned()
+          .ok_or_else(|| MalError::wrong_arguments("Missing argument for macroexpand"))?;
+        let expansion = self.macroexpand(form)?;
+        self.constant(expansion);
+      }
+      Some(symbol::TRY) => self.compile_try(list)?,
+      Some(sym) if self.scopes.is_macro(sym) => {
+        let expansion = self.macroexpand(MalType::List(list, span, None))?;
+        self.compile_form(expansion, tail)?;
+      }
+      _ => {
+        let count = list.len() - 1;
+        let form = MalType::List(list.clone(), span, None);
+        self.compile_form(list.pop_front().unwrap(), false)?;
+        // A macro defined later would be called as a function otherwise
+        let check = match head {
+          Some(sym) if self.scopes.resolve(sym).is_none() => {
+            self.chunk.constants.push(form);
+            Some(self.emit(Op::ExpandMacro(self.chunk.constants.len() - 1, 0)))
+          }
+          _ => None,
+        };
+        self.compile_all(list)?;
+        if tail {
+          self.emit(Op::TailCall(count));
+        } else {
+          self.emit(Op::Call(count));
+        }
+        if let Some(check) = check {
+          self.patch(check);
+        }
+      }
+    }
+    Ok(())
+  }
+
+  fn compile_let(&mut self, list: MalList, tail: bool) -> Result<(), MalError> {
+    let bindings = match list.get(1) {
+      Some(MalType::List(bindings, _, _)) | Some(MalType::Vector(bindings, _, _)) => {
+        bindings.clone()
+      }
+      _ => return Err(MalError::wrong_arguments("Expected list or vector")),
+    };
+    if bindings.len() % 2 != 0 {
+      return Err(MalError::unknown());
+    }
+    let names = bindings
+      .iter()
+      .step_by(2)
+      .map(|name| {
+        name
+          .symbol_value()
+          .ok_or_else(|| MalError::wrong_arguments("Expected symbol"))
+      })
+      .collect::<Result<Vec<Symbol>, MalError>>()?;
+    let body = list.get(2).cloned().ok_or_else(MalError::unknown)?;
+
+    self.chunk.frames.push(names.clone());
+    self.emit(Op::PushFrame(self.chunk.frames.len() - 1));
+    self.scopes.push(vec![]);
+    for (name, value) in names
+      .into_iter()
+      .zip(bindings.into_iter().skip(1).step_by(2))
+    {
+      self.compile_form(value, false)?;
+      self.emit(Op::SetSlot);
+      self.scopes.bind(name);
+    }
+    self.compile_form(body, tail)?;
+    self.scopes.pop();
+    self.emit(Op::PopFrame);
+    Ok(())
+  }
+
+  fn compile_fn(&mut self, list: MalList) -> Result<(), MalError> {
+    if list.len() < 3 {
+      return Err(MalError::generic("Not enough args to fn*, expecting 2"));
+    }
+    let params = match list[1].list_value() {
+      Some(params) => params,
+      None => {
+        return Err(MalError::generic(&format!(
+          "Expecting vector as first argument of fn*, but got: {:?}",
+          list[1]
+        )))
+      }
+    };
+    let names = params
+      .iter()
+      .filter_map(|param| param.symbol_value())
+      .filter(|name| *name != symbol::AMPERSAND)
+      .collect();
+    let body = list[2].clone();
+
+    self.scopes.push(names);
+    let mut compiler = Compiler {
+      chunk: Chunk::default(),
+      scopes: self.scopes,
+      span: self.span.clone(),
+    };
+    compiler.compile_guarded(body.clone(), true);
+    compiler.emit(Op::Return);
+    let code = Rc::new(compiler.chunk);
+    self.scopes.pop();
+
+    self.chunk.lambdas.push(Proto { params, body, code });
+    self.emit(Op::Closure(self.chunk.lambdas.len() - 1));
+    Ok(())
+  }
+
+  fn compile_try(&mut self, list: MalList) -> Result<(), MalError> {
+    let body = match list.get(1) {
+      Some(body) => body.clone(),
+      None => {
+        self.constant(MalType::Nil);
+        return Ok(());
+      }
+    };
+    let catch = match list.get(2) {
+      Some(catch) => catch.list_value().unwrap_or_default(),
+      None => return self.compile_form(body, false),
+    };
+    if !analyzer::is_catch(&catch) {
+      return Err(MalError::wrong_arguments(
+        "Expected (catch* symbol body) as second argument of try*",
+      ));
+    }
+    let name = catch[1].symbol_value().unwrap();
+
+    let to_handler = self.emit(Op::Try(0, name));
+    self.compile_form(body, false)?;
+    self.emit(Op::EndTry);
+    let to_end = self.emit(Op::Jump(0));
+    self.patch(to_handler);
+    self.scopes.push(vec![name]);
+    self.compile_form(catch[2].clone(), false)?;
+    self.scopes.pop();
+    self.emit(Op::PopFrame);
+    self.patch(to_end);
+    Ok(())
+  }
+
+  // Expands macro calls at the head of `form` until it no longer is one
+  fn macroexpand(&mut self, mut form: MalType) -> MalResult {
+    loop {
+      let mut list = match &form {
+        MalType::List(list, _, _) => list.clone(),
+        _ => return Ok(form),
+      };
+      let sym = match list.front().and_then(|head| head.symbol_value()) {
+        Some(sym) if self.scopes.is_macro(sym) => sym,
+        _ => return Ok(form),
+      };
+      let lambda = self.scopes.env().get(sym)?;
+      list.pop_front();
+      form = core::eval_func(lambda, &mut list.into_iter().collect())?;
+    }
+  }
+}
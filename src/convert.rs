@@ -0,0 +1,344 @@
+use std::collections::HashMap;
+
+use crate::number::Number;
+use crate::types::*;
+
+// Conversion of a MAL value into a Rust value, used to unpack native function arguments
+pub trait FromMal: Sized {
+  // Description of the accepted values, used in error messages
+  fn expected() -> String;
+
+  fn from_mal(value: MalType) -> Result<Self, MalError>;
+}
+
+// Conversion of a Rust value into a MAL value, used to return native function results
+pub trait IntoMal {
+  fn into_mal(self) -> MalType;
+}
+
+impl FromMal for MalType {
+  fn expected() -> String {
+    "any value".to_string()
+  }
+
+  fn from_mal(value: MalType) -> Result<Self, MalError> {
+    Ok(value)
+  }
+}
+
+impl IntoMal for MalType {
+  fn into_mal(self) -> MalType {
+    self
+  }
+}
+
+impl FromMal for i64 {
+  fn expected() -> String {
+    "integer".to_string()
+  }
+
+  fn from_mal(value: MalType) -> Result<Self, MalError> {
+    match value {
+      MalType::Number(n) => Ok(n),
+      value => Err(MalError::wrong_type(&Self::expected(), &value)),
+    }
+  }
+}
+
+impl IntoMal for i64 {
+  fn into_mal(self) -> MalType {
+    MalType::Number(self)
+  }
+}
+
+// Any number is accepted and converted, like Clojure's double coercion
+impl FromMal for f64 {
+  fn expected() -> String {
+    "number".to_string()
+  }
+
+  fn from_mal(value: MalType) -> Result<Self, MalError> {
+    match Number::from_mal(&value) {
+      Some(n) => Ok(n.to_f64()),
+      None => Err(MalError::wrong_type(&Self::expected(), &value)),
+    }
+  }
+}
+
+impl IntoMal for f64 {
+  fn into_mal(self) -> MalType {
+    MalType::Float(self)
+  }
+}
+
+impl FromMal for Number {
+  fn expected() -> String {
+    "number".to_string()
+  }
+
+  fn from_mal(value: MalType) -> Result<Self, MalError> {
+    match Number::from_mal(&value) {
+      Some(n) => Ok(n),
+      None => Err(MalError::wrong_type(&Self::expected(), &value)),
+    }
+  }
+}
+
+impl IntoMal for Number {
+  fn into_mal(self) -> MalType {
+    self.to_mal()
+  }
+}
+
+impl FromMal for bool {
+  fn expected() -> String {
+    "boolean".to_string()
+  }
+
+  fn from_mal(value: MalType) -> Result<Self, MalError> {
+    match value {
+      MalType::True => Ok(true),
+      MalType::False => Ok(false),
+      value => Err(MalError::wrong_type(&Self::expected(), &value)),
+    }
+  }
+}
+
+impl IntoMal for bool {
+  fn into_mal(self) -> MalType {
+    if self {
+      MalType::True
+    } else {
+      MalType::False
+    }
+  }
+}
+
+impl FromMal for String {
+  fn expected() -> String {
+    "string".to_string()
+  }
+
+  fn from_mal(value: MalType) -> Result<Self, MalError> {
+    match value {
+      MalType::String(s) => Ok(s),
+      value => Err(MalError::wrong_type(&Self::expected(), &value)),
+    }
+  }
+}
+
+impl IntoMal for String {
+  fn into_mal(self) -> MalType {
+    MalType::String(self)
+  }
+}
+
+impl IntoMal for &str {
+  fn into_mal(self) -> MalType {
+    MalType::String(self.to_string())
+  }
+}
+
+impl IntoMal for () {
+  fn into_mal(self) -> MalType {
+    MalType::Nil
+  }
+}
+
+impl<T: FromMal> FromMal for Vec<T> {
+  fn expected() -> String {
+    format!("list of {}", T::expected())
+  }
+
+  fn from_mal(value: MalType) -> Result<Self, MalError> {
+    match value {
+      MalType::List(list, _, _) | MalType::Vector(list, _, _) => {
+        list.into_iter().map(T::from_mal).collect()
+      }
+      value => Err(MalError::wrong_type(&Self::expected(), &value)),
+    }
+  }
+}
+
+impl<T: IntoMal> IntoMal for Vec<T> {
+  fn into_mal(self) -> MalType {
+    MalType::List(self.into_iter().map(T::into_mal).collect(), None, None)
+  }
+}
+
+// Sequences are passed through without copying their items
+impl FromMal for MalList {
+  fn expected() -> String {
+    "list".to_string()
+  }
+
+  fn from_mal(value: MalType) -> Result<Self, MalError> {
+    match value {
+      MalType::List(list, _, _) | MalType::Vector(list, _, _) => Ok(list),
+      value => Err(MalError::wrong_type(&Self::expected(), &value)),
+    }
+  }
+}
+
+impl IntoMal for MalList {
+  fn into_mal(self) -> MalType {
+    MalType::List(self, None, None)
+  }
+}
+
+impl<T: FromMal> FromMal for Option<T> {
+  fn expected() -> String {
+    format!("{} or nil", T::expected())
+  }
+
+  fn from_mal(value: MalType) -> Result<Self, MalError> {
+    match value {
+      MalType::Nil => Ok(None),
+      value => T::from_mal(value).map(Some),
+    }
+  }
+}
+
+impl<T: IntoMal> IntoMal for Option<T> {
+  fn into_mal(self) -> MalType {
+    match self {
+      Some(value) => value.into_mal(),
+      None => MalType::Nil,
+    }
+  }
+}
+
+impl FromMal for MalHashMap {
+  fn expected() -> String {
+    "hash-map".to_string()
+  }
+
+  fn from_mal(value: MalType) -> Result<Self, MalError> {
+    match value {
+      MalType::HashMap(map, _) => Ok(map),
+      value => Err(MalError::wrong_type(&Self::expected(), &value)),
+    }
+  }
+}
+
+impl IntoMal for MalHashMap {
+  fn into_mal(self) -> MalType {
+    MalType::HashMap(self, None)
+  }
+}
+
+// Keyword keys are accepted by name alongside string keys
+impl<T: FromMal> FromMal for HashMap<String, T> {
+  fn expected() -> String {
+    format!("hash-map of {}", T::expected())
+  }
+
+  fn from_mal(value: MalType) -> Result<Self, MalError> {
+    match value {
+      MalType::HashMap(map, _) => map
+        .into_iter()
+        .map(|(key, value)| {
+          let key = match key {
+            MalHashKey::String(s) | MalHashKey::Keyword(s) => s,
+          };
+          Ok((key, T::from_mal(value)?))
+        })
+        .collect(),
+      value => Err(MalError::wrong_type(&Self::expected(), &value)),
+    }
+  }
+}
+
+impl<T: IntoMal> IntoMal for HashMap<String, T> {
+  fn into_mal(self) -> MalType {
+    let map = self
+      .into_iter()
+      .map(|(key, value)| (MalHashKey::String(key), value.into_mal()))
+      .collect();
+    MalType::HashMap(map, None)
+  }
+}
+
+// Tuples convert from lists or vectors of exactly their length
+macro_rules! impl_tuple {
+  ($len:expr; $($name:ident),+) => {
+    impl<$($name: FromMal),+> FromMal for ($($name,)+) {
+      fn expected() -> String {
+        format!("list of ({})", vec![$($name::expected()),+].join(", "))
+      }
+
+      fn from_mal(value: MalType) -> Result<Self, MalError> {
+        let list = match value {
+          MalType::List(list, _, _) | MalType::Vector(list, _, _) if list.len() == $len => list,
+          value => return Err(MalError::wrong_type(&Self::expected(), &value)),
+        };
+        let mut items = list.into_iter();
+        Ok(($($name::from_mal(items.next().unwrap())?,)+))
+      }
+    }
+
+    impl<$($name: IntoMal),+> IntoMal for ($($name,)+) {
+      #[allow(non_snake_case)]
+      fn into_mal(self) -> MalType {
+        let ($($name,)+) = self;
+        MalType::List(vec![$($name.into_mal()),+].into(), None, None)
+      }
+    }
+  };
+}
+
+impl_tuple!(2; A, B);
+impl_tuple!(3; A, B, C);
+impl_tuple!(4; A, B, C, D);
+
+// A Rust function whose arguments and result convert to and from MAL values.
+// `Args` is the tuple of argument types, which keeps the impls for each arity apart.
+pub trait TypedFunction<Args> {
+  fn arity(&self) -> Arity;
+
+  fn call_typed(&self, args: Vec<MalType>) -> MalResult;
+}
+
+macro_rules! impl_typed_function {
+  ($len:expr; $($arg:ident),*) => {
+    impl<F, R, E, $($arg),*> TypedFunction<($($arg,)*)> for F
+    where
+      F: Fn($($arg),*) -> Result<R, E>,
+      $($arg: FromMal,)*
+      R: IntoMal,
+      E: Into<MalError>,
+    {
+      fn arity(&self) -> Arity {
+        Arity::exact($len)
+      }
+
+      #[allow(non_snake_case, unused_mut, unused_variables)]
+      fn call_typed(&self, args: Vec<MalType>) -> MalResult {
+        let mut args = args.into_iter();
+        $(let $arg = $arg::from_mal(args.next().unwrap())?;)*
+        match self($($arg),*) {
+          Ok(value) => Ok(value.into_mal()),
+          Err(err) => Err(err.into()),
+        }
+      }
+    }
+  };
+}
+
+impl_typed_function!(0;);
+impl_typed_function!(1; A);
+impl_typed_function!(2; A, B);
+impl_typed_function!(3; A, B, C);
+impl_typed_function!(4; A, B, C, D);
+impl_typed_function!(5; A, B, C, D, G);
+
+// Wraps a typed Rust function as a native function that checks argument types
+pub fn typed_function<Args, F>(name: &str, func: F) -> MalFunc
+where
+  F: TypedFunction<Args> + 'static,
+{
+  let arity = func.arity();
+  MalFunc::new(name, arity, move |args| {
+    func.call_typed(std::mem::take(args))
+  })
+}
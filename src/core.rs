@@ -1,313 +1,736 @@
+use crate::convert::FromMal;
 use crate::env::Env;
+use crate::interpreter;
+use crate::limits;
+use crate::number::Number;
 use crate::printer;
 use crate::reader;
 use crate::types::*;
+use crate::vm;
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
+use std::cmp::Ordering;
 use std::collections::HashMap;
-use std::convert::TryInto;
 use std::fs;
+use std::io::{self, Read};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Items of a list that changing it may copy, when it shares them with
+// another list. Collections are stored in chunks of this many items.
+const CHUNK_SIZE: usize = 64;
+
+// A native function exposed to mal, along with what `doc` shows for it
+pub struct CoreDef {
+  pub name: &'static str,
+  pub params: &'static [&'static str],
+  pub doc: &'static str,
+  pub arity: Arity,
+  pub func: CoreFunction,
+  // What the function reaches outside the interpreter, if anything
+  pub access: Option<Access>,
+}
+
+// Access to the host that a core function needs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+  // Reads the file at the path given as its first argument
+  Files,
+  // Uses the terminal or the clock
+  Host,
+}
+
+impl CoreDef {
+  pub fn to_mal(&self) -> MalType {
+    MalType::Function(MalFunc::new(self.name, self.arity, self.func))
+  }
+}
 
-macro_rules! gen_functions_map {
-  ($( $sym:expr => $func:ident ),*) => {
+// Declares core functions as a docstring followed by
+// `"name" => func(param: Type, & rest: Type)`, and `needs Access` for those
+// reaching outside the interpreter. Each entry gets a wrapper that converts
+// the arguments with FromMal before calling `func`; MalFunc checks the arity.
+macro_rules! core_functions {
+  ($( $(#[doc = $doc:expr])* $sym:literal => $func:ident ( $($params:tt)* )
+      $(needs $access:ident)? ),* $(,)?) => {
     {
       let mut map = HashMap::new();
       $(
-        map.insert($sym, $func as CoreFunction);
+        map.insert($sym, CoreDef {
+          access: core_functions!(@access $($access)?),
+          ..core_function!($sym, $func, concat!($($doc, "\n"),*), [] $($params)*)
+        });
       )*
       map
     }
   };
+  (@access) => { None };
+  (@access $access:ident) => { Some(Access::$access) };
+}
+
+macro_rules! core_function {
+  (@build $sym:literal, $func:ident, $doc:expr,
+    [$(($name:ident : $ty:ty))*] [$(($rest:ident : $rest_ty:ty))?]) => {
+    CoreDef {
+      name: $sym,
+      params: &[$(stringify!($name),)* $("&", stringify!($rest))?],
+      doc: $doc,
+      arity: core_function!(@arity [$($name)*] [$($rest)?]),
+      access: None,
+      func: |args: &mut Vec<MalType>| -> MalResult {
+        #[allow(unused_mut, unused_variables)]
+        let mut args = std::mem::take(args).into_iter();
+        $(let $name = <$ty as FromMal>::from_mal(args.next().unwrap())?;)*
+        $(let $rest = args.map(<$rest_ty as FromMal>::from_mal).collect::<Result<Vec<_>, _>>()?;)?
+        $func($($name,)* $($rest)?)
+      },
+    }
+  };
+  (@arity [$($name:ident)*] []) => {
+    Arity::exact(<[&str]>::len(&[$(stringify!($name)),*]))
+  };
+  (@arity [$($name:ident)*] [$rest:ident]) => {
+    Arity::at_least(<[&str]>::len(&[$(stringify!($name)),*]))
+  };
+  // Munch the parameter list into fixed parameters and an optional variadic tail
+  ($sym:literal, $func:ident, $doc:expr, [$($fixed:tt)*] $name:ident : $ty:ty, $($tail:tt)*) => {
+    core_function!($sym, $func, $doc, [$($fixed)* ($name : $ty)] $($tail)*)
+  };
+  ($sym:literal, $func:ident, $doc:expr, [$($fixed:tt)*] $name:ident : $ty:ty) => {
+    core_function!(@build $sym, $func, $doc, [$($fixed)* ($name : $ty)] [])
+  };
+  ($sym:literal, $func:ident, $doc:expr, [$($fixed:tt)*] & $rest:ident : $rest_ty:ty) => {
+    core_function!(@build $sym, $func, $doc, [$($fixed)*] [($rest : $rest_ty)])
+  };
+  ($sym:literal, $func:ident, $doc:expr, [$($fixed:tt)*]) => {
+    core_function!(@build $sym, $func, $doc, [$($fixed)*] [])
+  };
 }
 
 lazy_static! {
-  pub static ref CORE_FUNCTIONS: HashMap<&'static str, CoreFunction> = {
-    gen_functions_map! {
-      "+" => plus,
-      "-" => minus,
-      "*" => multiply,
-      "/" => divide,
-      "list" => list,
-      "list?" => is_list,
-      "empty?" => is_empty,
-      "count" => count,
-      "=" => equal,
-      "<" => less_than,
-      "<=" => less_than_or_eq,
-      ">" => greater_than,
-      ">=" => greater_than_or_eq,
-      "prn" => prn,
-      "println" => println,
-      "pr-str" => pr_str,
-      "str" => str,
-      "read-string" => read_string,
-      "slurp" => slurp,
-      "atom" => atom,
-      "atom?" => is_atom,
-      "deref" => deref,
-      "reset!" => reset,
-      "swap!" => swap,
-      "cons" => cons,
-      "concat" => concat
+  pub static ref CORE_FUNCTIONS: HashMap<&'static str, CoreDef> = {
+    core_functions! {
+      /// Returns the sum of nums, or 0 when there are none
+      "+" => plus(& nums: Number),
+      /// Subtracts the remaining nums from x, or negates x when alone
+      "-" => minus(x: Number, & nums: Number),
+      /// Returns the product of nums, or 1 when there are none
+      "*" => multiply(& nums: Number),
+      /// Divides x by the remaining nums, or returns its reciprocal when alone.
+      /// Exact division produces a ratio when the result is not an integer.
+      "/" => divide(x: Number, & nums: Number),
+      /// Rounds n down to the nearest integer
+      "floor" => floor(n: Number),
+      /// Rounds n up to the nearest integer
+      "ceil" => ceil(n: Number),
+      /// Rounds n to the nearest integer, halves away from zero
+      "round" => round(n: Number),
+      /// Returns the square root of n as a float
+      "sqrt" => sqrt(n: Number),
+      /// Raises base to exponent as a float
+      "pow" => pow(base: Number, exponent: Number),
+      /// Returns the numerator of a ratio or integer
+      "numerator" => numerator(n: Number),
+      /// Returns the denominator of a ratio or integer
+      "denominator" => denominator(n: Number),
+      /// Returns a list of the given items
+      "list" => list(& items: MalType),
+      /// Returns true if value is a list
+      "list?" => is_list(value: MalType),
+      /// Returns true if coll has no items
+      "empty?" => is_empty(coll: Option<MalList>),
+      /// Returns the number of items in coll, counting nil as empty
+      "count" => count(coll: Option<MalList>),
+      /// Returns true if a and b are equal values
+      "=" => equal(a: MalType, b: MalType),
+      /// Returns true if a is less than b
+      "<" => less_than(a: Number, b: Number),
+      /// Returns true if a is less than or equal to b
+      "<=" => less_than_or_eq(a: Number, b: Number),
+      /// Returns true if a is greater than b
+      ">" => greater_than(a: Number, b: Number),
+      /// Returns true if a is greater than or equal to b
+      ">=" => greater_than_or_eq(a: Number, b: Number),
+      /// Prints values readably, separated by spaces
+      "prn" => prn(& values: MalType),
+      /// Prints values for humans, separated by spaces
+      "println" => println(& values: MalType),
+      /// Returns values printed readably and separated by spaces
+      "pr-str" => pr_str(& values: MalType),
+      /// Returns values printed for humans and concatenated
+      "str" => str(& values: MalType),
+      /// Reads the first form in source
+      "read-string" => read_string(source: String),
+      /// Returns the contents of the file at path
+      "slurp" => slurp(path: String) needs Files,
+      /// Reads every form in the file at path into a single do form
+      "read-file" => read_file(path: String) needs Files,
+      /// Returns an atom holding value
+      "atom" => atom(value: MalType),
+      /// Returns true if value is an atom
+      "atom?" => is_atom(value: MalType),
+      /// Returns the value held by atom
+      "deref" => deref(atom: MalType),
+      /// Sets the value held by atom and returns it
+      "reset!" => reset(atom: MalType, value: MalType),
+      /// Sets atom to (f current args...) and returns the new value
+      "swap!" => swap(atom: MalType, f: MalType, & args: MalType),
+      /// Returns a list of value followed by the items of coll
+      "cons" => cons(value: MalType, coll: MalList),
+      /// Returns a list of the items of every coll in order
+      "concat" => concat(& colls: MalList),
+      /// Returns the item of coll at index
+      "nth" => nth(coll: MalList, index: i64),
+      /// Returns the first item of coll, or nil when empty
+      "first" => first(coll: Option<MalList>),
+      /// Returns a list of every item of coll but the first
+      "rest" => rest(coll: Option<MalList>),
+      /// Raises value as an exception for try*/catch*
+      "throw" => throw(value: MalType),
+      /// Calls f with args followed by the items of the last argument
+      "apply" => apply(f: MalType, & args: MalType),
+      /// Returns a list of the results of calling f on each item of coll
+      "map" => map(f: MalType, coll: MalList),
+      /// Returns true if value is nil
+      "nil?" => is_nil(value: MalType),
+      /// Returns true if value is true
+      "true?" => is_true(value: MalType),
+      /// Returns true if value is false
+      "false?" => is_false(value: MalType),
+      /// Returns true if value is a symbol
+      "symbol?" => is_symbol(value: MalType),
+      /// Returns the symbol with the given name
+      "symbol" => symbol(name: String),
+      /// Returns the keyword with the given name, or name itself when a keyword
+      "keyword" => keyword(name: MalType),
+      /// Returns true if value is a keyword
+      "keyword?" => is_keyword(value: MalType),
+      /// Returns a vector of the given items
+      "vector" => vector(& items: MalType),
+      /// Returns true if value is a vector
+      "vector?" => is_vector(value: MalType),
+      /// Returns true if value is a list or vector
+      "sequential?" => is_sequential(value: MalType),
+      /// Returns a hash-map of the given keys and values
+      "hash-map" => hash_map(& kvs: MalType),
+      /// Returns true if value is a hash-map
+      "map?" => is_map(value: MalType),
+      /// Returns map with the given keys set to their values
+      "assoc" => assoc(map: MalHashMap, & kvs: MalType),
+      /// Returns map without the given keys
+      "dissoc" => dissoc(map: MalHashMap, & keys: MalType),
+      /// Returns the value of key in map, or nil when missing
+      "get" => get(map: Option<MalHashMap>, key: MalType),
+      /// Returns true if map has key
+      "contains?" => contains(map: MalHashMap, key: MalType),
+      /// Returns a list of the keys of map
+      "keys" => keys(map: MalHashMap),
+      /// Returns a list of the values of map
+      "vals" => vals(map: MalHashMap),
+      /// Reads a line from the terminal, or nil at end of input
+      "readline" => readline(prompt: String) needs Host,
+      /// Returns the current time in milliseconds since the epoch
+      "time-ms" => time_ms() needs Host,
+      /// Returns the metadata of a function, collection or atom, or nil
+      "meta" => meta(value: MalType),
+      /// Returns a copy of a function, collection or atom with the given metadata
+      "with-meta" => with_meta(value: MalType, meta: MalType),
+      /// Returns true if value is a string
+      "string?" => is_string(value: MalType),
+      /// Returns true if value is a number
+      "number?" => is_number(value: MalType),
+      /// Returns true if value is a function
+      "fn?" => is_fn(value: MalType),
+      /// Returns true if value is a macro
+      "macro?" => is_macro(value: MalType),
+      /// Returns coll as a list, or nil when empty. Strings become lists of characters.
+      "seq" => seq(coll: MalType),
+      /// Adds items to coll, at the front of lists and the end of vectors
+      "conj" => conj(coll: MalType, & items: MalType),
+      /// Prints the parameters and documentation of a function
+      "doc" => doc(f: MalType),
     }
   };
 }
 
-pub fn plus(args: &mut Vec<MalType>, _env: Option<Env>) -> MalResult {
-  let result = to_numbers(args)?.iter().fold(0, |acc, x| acc + x);
-  Ok(MalType::Number(result))
+pub fn plus(nums: Vec<Number>) -> MalResult {
+  let result = nums.into_iter().fold(Number::Int(0), |acc, x| acc.plus(x));
+  Ok(result.to_mal())
 }
 
-pub fn minus(args: &mut Vec<MalType>, _env: Option<Env>) -> MalResult {
-  let mut args = to_numbers(args)?;
-  let mut result = args.remove(0);
-  for i in args {
-    result -= i;
+pub fn minus(x: Number, nums: Vec<Number>) -> MalResult {
+  if nums.is_empty() {
+    return Ok(Number::Int(0).minus(x).to_mal());
   }
-  Ok(MalType::Number(result))
+  let result = nums.into_iter().fold(x, |acc, n| acc.minus(n));
+  Ok(result.to_mal())
 }
 
-pub fn multiply(args: &mut Vec<MalType>, _env: Option<Env>) -> MalResult {
-  let result = to_numbers(args)?.iter().fold(1, |acc, x| acc * x);
-  Ok(MalType::Number(result))
+pub fn multiply(nums: Vec<Number>) -> MalResult {
+  let result = nums
+    .into_iter()
+    .fold(Number::Int(1), |acc, x| acc.multiply(x));
+  Ok(result.to_mal())
 }
 
-pub fn divide(args: &mut Vec<MalType>, _env: Option<Env>) -> MalResult {
-  let mut args = to_numbers(args)?;
-  let mut result = args.remove(0);
-  for i in args {
-    result /= i;
+pub fn divide(x: Number, nums: Vec<Number>) -> MalResult {
+  if nums.is_empty() {
+    return Ok(Number::Int(1).divide(x)?.to_mal());
   }
-  Ok(MalType::Number(result))
+  let mut result = x;
+  for n in nums {
+    result = result.divide(n)?;
+  }
+  Ok(result.to_mal())
 }
 
-pub fn list(args: &mut Vec<MalType>, _env: Option<Env>) -> MalResult {
-  Ok(MalType::List(args.to_owned()))
+pub fn floor(n: Number) -> MalResult {
+  Ok(n.floor().to_mal())
 }
 
-pub fn is_list(args: &mut Vec<MalType>, _env: Option<Env>) -> MalResult {
-  if args.len() == 0 {
-    return Ok(MalType::False);
-  }
+pub fn ceil(n: Number) -> MalResult {
+  Ok(n.ceil().to_mal())
+}
+
+pub fn round(n: Number) -> MalResult {
+  Ok(n.round().to_mal())
+}
 
-  Ok(MalType::to_bool(args[0].is_list()))
+pub fn sqrt(n: Number) -> MalResult {
+  Ok(n.sqrt().to_mal())
 }
 
-pub fn is_empty(args: &mut Vec<MalType>, _env: Option<Env>) -> MalResult {
-  expected_arguments(args, 1)?;
-  match args[0].list_value() {
-    Some(list) => Ok(MalType::to_bool(list.len() == 0)),
-    None => Err(MalError::wrong_arguments("Not a list")),
+pub fn pow(base: Number, exponent: Number) -> MalResult {
+  Ok(base.pow(exponent).to_mal())
+}
+
+pub fn numerator(n: Number) -> MalResult {
+  match n.numerator() {
+    Some(n) => Ok(n.to_mal()),
+    None => Err(MalError::wrong_arguments("Expected a rational number")),
   }
 }
 
-pub fn count(args: &mut Vec<MalType>, _env: Option<Env>) -> MalResult {
-  expected_arguments(args, 1)?;
-  if args[0].is_nil() {
-    return Ok(MalType::Number(0));
+pub fn denominator(n: Number) -> MalResult {
+  match n.denominator() {
+    Some(n) => Ok(n.to_mal()),
+    None => Err(MalError::wrong_arguments("Expected a rational number")),
   }
-  match args[0].list_value() {
-    Some(list) => Ok(MalType::Number(list.len().try_into().unwrap())),
-    None => Err(MalError::wrong_arguments("Not a list")),
+}
+
+pub fn list(items: Vec<MalType>) -> MalResult {
+  limits::allocate_items(items.len())?;
+  Ok(MalType::List(items.into(), None, None))
+}
+
+pub fn is_list(value: MalType) -> MalResult {
+  Ok(MalType::to_bool(value.is_list()))
+}
+
+pub fn is_empty(coll: Option<MalList>) -> MalResult {
+  Ok(MalType::to_bool(coll.unwrap_or_default().is_empty()))
+}
+
+pub fn count(coll: Option<MalList>) -> MalResult {
+  Ok(MalType::Number(coll.unwrap_or_default().len() as i64))
+}
+
+pub fn equal(a: MalType, b: MalType) -> MalResult {
+  Ok(MalType::to_bool(values_equal(&a, &b)))
+}
+
+pub fn less_than(a: Number, b: Number) -> MalResult {
+  Ok(MalType::to_bool(a.compare(&b) == Some(Ordering::Less)))
+}
+
+pub fn less_than_or_eq(a: Number, b: Number) -> MalResult {
+  Ok(MalType::to_bool(matches!(
+    a.compare(&b),
+    Some(Ordering::Less) | Some(Ordering::Equal)
+  )))
+}
+
+pub fn greater_than(a: Number, b: Number) -> MalResult {
+  Ok(MalType::to_bool(a.compare(&b) == Some(Ordering::Greater)))
+}
+
+pub fn greater_than_or_eq(a: Number, b: Number) -> MalResult {
+  Ok(MalType::to_bool(matches!(
+    a.compare(&b),
+    Some(Ordering::Greater) | Some(Ordering::Equal)
+  )))
+}
+
+pub fn prn(values: Vec<MalType>) -> MalResult {
+  print(&values, true)
+}
+
+pub fn println(values: Vec<MalType>) -> MalResult {
+  print(&values, false)
+}
+
+pub fn pr_str(values: Vec<MalType>) -> MalResult {
+  Ok(MalType::String(join(&values, " ", true)?))
+}
+
+pub fn str(values: Vec<MalType>) -> MalResult {
+  Ok(MalType::String(join(&values, "", false)?))
+}
+
+pub fn read_string(source: String) -> MalResult {
+  reader::read_str(source)
+}
+
+pub fn read_file(path: String) -> MalResult {
+  let contents = read_allocated(&path)?;
+  reader::read_file(contents, &path)
+}
+
+pub fn slurp(path: String) -> MalResult {
+  Ok(MalType::String(read_allocated(&path)?))
+}
+
+pub fn atom(value: MalType) -> MalResult {
+  Ok(MalType::atom(value))
+}
+
+pub fn is_atom(value: MalType) -> MalResult {
+  Ok(MalType::to_bool(value.is_atom()))
+}
+
+pub fn deref(atom: MalType) -> MalResult {
+  match atom {
+    MalType::Atom(value, _) => Ok(value.borrow().to_owned()),
+    value => Err(MalError::wrong_type("atom", &value)),
   }
 }
 
-pub fn equal(args: &mut Vec<MalType>, _env: Option<Env>) -> MalResult {
-  expected_arguments(args, 2)?;
-  Ok(MalType::to_bool(values_equal(&args[0], &args[1])))
+pub fn reset(atom: MalType, value: MalType) -> MalResult {
+  match atom {
+    MalType::Atom(atom, _) => atom.replace(value.clone()),
+    value => return Err(MalError::wrong_type("atom", &value)),
+  };
+  Ok(value)
 }
 
-pub fn less_than(args: &mut Vec<MalType>, _env: Option<Env>) -> MalResult {
-  expected_arguments(args, 2)?;
-  let first = get_number(&args[0])?;
-  let second = get_number(&args[1])?;
-  Ok(MalType::to_bool(first < second))
+pub fn swap(mut atom: MalType, f: MalType, mut args: Vec<MalType>) -> MalResult {
+  atom.swap(f, &mut args)
 }
 
-pub fn less_than_or_eq(args: &mut Vec<MalType>, _env: Option<Env>) -> MalResult {
-  expected_arguments(args, 2)?;
-  let first = get_number(&args[0])?;
-  let second = get_number(&args[1])?;
-  Ok(MalType::to_bool(first <= second))
+pub fn cons(value: MalType, mut coll: MalList) -> MalResult {
+  limits::allocate_items(1)?;
+  limits::allocate_copies(|| coll.iter().take(CHUNK_SIZE))?;
+  coll.push_front(value);
+  Ok(MalType::List(coll, None, None))
 }
 
-pub fn greater_than(args: &mut Vec<MalType>, _env: Option<Env>) -> MalResult {
-  expected_arguments(args, 2)?;
-  let first = get_number(&args[0])?;
-  let second = get_number(&args[1])?;
-  Ok(MalType::to_bool(first > second))
+pub fn concat(colls: Vec<MalList>) -> MalResult {
+  limits::allocate_copies(|| colls.iter().flatten())?;
+  let mut result = MalList::new();
+  for coll in colls {
+    result.append(coll);
+  }
+  Ok(MalType::List(result, None, None))
 }
 
-pub fn greater_than_or_eq(args: &mut Vec<MalType>, _env: Option<Env>) -> MalResult {
-  expected_arguments(args, 2)?;
-  let first = get_number(&args[0])?;
-  let second = get_number(&args[1])?;
-  Ok(MalType::to_bool(first >= second))
+pub fn nth(coll: MalList, index: i64) -> MalResult {
+  if index < 0 {
+    return Err(MalError::generic("Index out of range"));
+  }
+  match coll.get(index as usize) {
+    Some(value) => Ok(value.to_owned()),
+    None => Err(MalError::generic("Index out of range")),
+  }
 }
 
-pub fn prn(args: &mut Vec<MalType>, _env: Option<Env>) -> MalResult {
-  print(args, true)?;
-  Ok(MalType::Nil)
+pub fn first(coll: Option<MalList>) -> MalResult {
+  match coll.unwrap_or_default().pop_front() {
+    Some(value) => Ok(value),
+    None => Ok(MalType::Nil),
+  }
 }
 
-pub fn println(args: &mut Vec<MalType>, _env: Option<Env>) -> MalResult {
-  print(args, false)?;
-  Ok(MalType::Nil)
+pub fn rest(coll: Option<MalList>) -> MalResult {
+  let mut rest = coll.unwrap_or_default();
+  limits::allocate_copies(|| rest.iter().take(CHUNK_SIZE))?;
+  rest.pop_front();
+  Ok(MalType::List(rest, None, None))
 }
 
-pub fn pr_str(args: &mut Vec<MalType>, _env: Option<Env>) -> MalResult {
-  Ok(MalType::String(join(args, " ", true)))
+pub fn throw(value: MalType) -> MalResult {
+  Err(MalError::exception(value))
 }
 
-pub fn str(args: &mut Vec<MalType>, _env: Option<Env>) -> MalResult {
-  Ok(MalType::String(join(args, "", false)))
+pub fn apply(f: MalType, mut args: Vec<MalType>) -> MalResult {
+  let last = match args.pop() {
+    Some(last) => last,
+    None => return Err(MalError::wrong_arity("apply", 1, Arity::at_least(2))),
+  };
+  let mut last = Vec::<MalType>::from_mal(last)?;
+  limits::allocate_items(last.len())?;
+  args.append(&mut last);
+  Ok(MalType::tail_call(f, args))
 }
 
-pub fn read_string(args: &mut Vec<MalType>, _env: Option<Env>) -> MalResult {
-  expected_arguments(args, 1)?;
-  let arg = args.get(0).expect("Somehow lost an argument");
-  if let Some(arg) = arg.string_value() {
-    reader::read_str(arg)
-  } else {
-    Err(MalError::generic("Not a string"))
+pub fn map(f: MalType, coll: MalList) -> MalResult {
+  limits::allocate_items(coll.len())?;
+  let mut results = MalList::new();
+  for item in coll {
+    results.push_back(eval_func(f.clone(), &mut vec![item])?);
   }
+  Ok(MalType::List(results, None, None))
 }
 
-pub fn slurp(args: &mut Vec<MalType>, _env: Option<Env>) -> MalResult {
-  expected_arguments(args, 1)?;
-  let arg = args.get(0).expect("Somehow lost an argument");
-  if let Some(arg) = arg.string_value() {
-    match fs::read_to_string(arg) {
-      Ok(contents) => Ok(MalType::String(contents)),
-      Err(err) => Err(MalError::generic(&err.to_string())),
-    }
-  } else {
-    Err(MalError::generic("Not a string"))
+pub fn is_nil(value: MalType) -> MalResult {
+  Ok(MalType::to_bool(value.is_nil()))
+}
+
+pub fn is_true(value: MalType) -> MalResult {
+  Ok(MalType::to_bool(matches!(value, MalType::True)))
+}
+
+pub fn is_false(value: MalType) -> MalResult {
+  Ok(MalType::to_bool(matches!(value, MalType::False)))
+}
+
+pub fn is_symbol(value: MalType) -> MalResult {
+  Ok(MalType::to_bool(value.is_symbol()))
+}
+
+pub fn symbol(name: String) -> MalResult {
+  limits::allocate(name.len())?;
+  Ok(MalType::Symbol(Symbol::intern(&name), None))
+}
+
+pub fn keyword(name: MalType) -> MalResult {
+  match name {
+    MalType::Keyword(_) => Ok(name),
+    MalType::String(name) => Ok(MalType::Keyword(name)),
+    value => Err(MalError::wrong_type("string or keyword", &value)),
   }
 }
 
-pub fn atom(args: &mut Vec<MalType>, _env: Option<Env>) -> MalResult {
-  expected_arguments(args, 1)?;
-  let arg = args.get(0).expect("Somehow lost an argument");
-  Ok(MalType::atom(arg.to_owned()))
+pub fn is_keyword(value: MalType) -> MalResult {
+  Ok(MalType::to_bool(value.is_keyword()))
+}
+
+pub fn vector(items: Vec<MalType>) -> MalResult {
+  limits::allocate_items(items.len())?;
+  Ok(MalType::Vector(items.into(), None, None))
+}
+
+pub fn is_vector(value: MalType) -> MalResult {
+  Ok(MalType::to_bool(value.is_vector()))
+}
+
+pub fn is_sequential(value: MalType) -> MalResult {
+  Ok(MalType::to_bool(value.is_list_or_vector()))
+}
+
+pub fn hash_map(kvs: Vec<MalType>) -> MalResult {
+  let map = assoc_pairs(MalHashMap::new(), &kvs)?;
+  Ok(MalType::HashMap(map, None))
 }
 
-pub fn is_atom(args: &mut Vec<MalType>, _env: Option<Env>) -> MalResult {
-  expected_arguments(args, 1)?;
-  let arg = args.get(0).expect("Somehow lost an argument");
-  Ok(MalType::to_bool(arg.is_atom()))
+pub fn is_map(value: MalType) -> MalResult {
+  Ok(MalType::to_bool(value.is_map()))
 }
 
-pub fn deref(args: &mut Vec<MalType>, _env: Option<Env>) -> MalResult {
-  expected_arguments(args, 1)?;
-  let arg = args.get(0).expect("Somehow lost an argument");
-  match arg {
-    MalType::Atom(value) => Ok(value.borrow().to_owned()),
-    _ => Err(MalError::wrong_arguments("Not an atom")),
+pub fn assoc(map: MalHashMap, kvs: Vec<MalType>) -> MalResult {
+  let map = assoc_pairs(map, &kvs)?;
+  Ok(MalType::HashMap(map, None))
+}
+
+pub fn dissoc(mut map: MalHashMap, keys: Vec<MalType>) -> MalResult {
+  for key in keys.iter() {
+    map.remove(&hash_key(key)?);
   }
+  Ok(MalType::HashMap(map, None))
 }
 
-pub fn reset(args: &mut Vec<MalType>, _env: Option<Env>) -> MalResult {
-  expected_arguments(args, 2)?;
-  let atom = args.remove(0);
-  let value = args.remove(0);
-  match atom {
-    MalType::Atom(atom) => atom.replace(value.clone()),
-    _ => return Err(MalError::wrong_arguments("Not an atom")),
+pub fn get(map: Option<MalHashMap>, key: MalType) -> MalResult {
+  let map = match map {
+    Some(map) => map,
+    None => return Ok(MalType::Nil),
   };
-  Ok(value)
+  match map.get(&hash_key(&key)?) {
+    Some(value) => Ok(value.to_owned()),
+    None => Ok(MalType::Nil),
+  }
+}
+
+pub fn contains(map: MalHashMap, key: MalType) -> MalResult {
+  Ok(MalType::to_bool(map.contains_key(&hash_key(&key)?)))
+}
+
+pub fn keys(map: MalHashMap) -> MalResult {
+  limits::allocate_items(map.len())?;
+  Ok(MalType::List(
+    map.keys().map(|k| k.to_owned().into()).collect(),
+    None,
+    None,
+  ))
+}
+
+pub fn vals(map: MalHashMap) -> MalResult {
+  limits::allocate_copies(|| map.values())?;
+  Ok(MalType::List(map.values().cloned().collect(), None, None))
+}
+
+pub fn readline(prompt: String) -> MalResult {
+  let mut rl = Editor::<()>::new();
+  match rl.readline(&prompt) {
+    Ok(line) => {
+      limits::allocate(line.len())?;
+      Ok(MalType::String(
+        line.trim_end_matches(&['\r', '\n'][..]).to_string(),
+      ))
+    }
+    Err(ReadlineError::Eof) | Err(ReadlineError::Interrupted) => Ok(MalType::Nil),
+    Err(err) => Err(MalError::generic(&err.to_string())),
+  }
+}
+
+pub fn time_ms() -> MalResult {
+  match SystemTime::now().duration_since(UNIX_EPOCH) {
+    Ok(duration) => Ok(MalType::Number(duration.as_millis() as i64)),
+    Err(err) => Err(MalError::generic(&err.to_string())),
+  }
+}
+
+pub fn meta(value: MalType) -> MalResult {
+  Ok(value.meta())
+}
+
+pub fn with_meta(value: MalType, meta: MalType) -> MalResult {
+  value.with_meta(meta)
+}
+
+pub fn is_string(value: MalType) -> MalResult {
+  Ok(MalType::to_bool(value.is_string()))
+}
+
+pub fn is_number(value: MalType) -> MalResult {
+  Ok(MalType::to_bool(value.is_number()))
+}
+
+pub fn is_fn(value: MalType) -> MalResult {
+  Ok(MalType::to_bool(value.is_function()))
 }
 
-pub fn swap(args: &mut Vec<MalType>, _env: Option<Env>) -> MalResult {
-  expected_arguments(args, 2)?;
-  let mut atom = args.remove(0);
-  let func = args.remove(0);
-  atom.swap(func, args)
+pub fn is_macro(value: MalType) -> MalResult {
+  Ok(MalType::to_bool(value.is_macro()))
 }
 
-pub fn cons(args: &mut Vec<MalType>, _env: Option<Env>) -> MalResult {
-  expected_arguments(args, 2)?;
-  let value = args.remove(0);
-  let list = args.remove(0);
-  if let Some(list) = list.list_value() {
-    let mut list = list.clone();
-    list.insert(0, value);
-    Ok(MalType::List(list))
+pub fn seq(coll: MalType) -> MalResult {
+  let list = match coll {
+    MalType::Nil => return Ok(MalType::Nil),
+    MalType::String(s) => {
+      limits::allocate_items(s.len())?;
+      s.chars().map(|c| MalType::String(c.to_string())).collect()
+    }
+    value => MalList::from_mal(value)?,
+  };
+  if list.is_empty() {
+    Ok(MalType::Nil)
   } else {
-    Err(MalError::wrong_arguments("2nd argument was not a list"))
+    Ok(MalType::List(list, None, None))
   }
 }
 
-pub fn concat(args: &mut Vec<MalType>, _env: Option<Env>) -> MalResult {
-  let mut outlist = vec![];
-  for arg in args {
-    let mut list = vec_value(arg)?;
-    outlist.append(&mut list);
+pub fn conj(coll: MalType, items: Vec<MalType>) -> MalResult {
+  limits::allocate_items(items.len())?;
+  match coll {
+    MalType::List(mut list, _, _) => {
+      limits::allocate_copies(|| list.iter().take(CHUNK_SIZE))?;
+      for value in items {
+        list.push_front(value);
+      }
+      Ok(MalType::List(list, None, None))
+    }
+    MalType::Vector(mut list, _, _) => {
+      limits::allocate_copies(|| list.iter().rev().take(CHUNK_SIZE))?;
+      list.extend(items);
+      Ok(MalType::Vector(list, None, None))
+    }
+    value => Err(MalError::wrong_type("list or vector", &value)),
   }
-  Ok(MalType::List(outlist))
+}
+
+pub fn doc(f: MalType) -> MalResult {
+  let (name, params, doc) = match &f {
+    MalType::Function(func) => match CORE_FUNCTIONS.get(func.name.as_str()) {
+      Some(def) => (def.name.to_string(), def.params.join(" "), def.doc),
+      None => (func.name.to_owned(), format!("{} args", func.arity), ""),
+    },
+    MalType::Lambda(lambda) => {
+      let params: Vec<String> = lambda
+        .args
+        .iter()
+        .map(|arg| printer::print_str(arg, false))
+        .collect();
+      let name = lambda.name.to_owned().unwrap_or_default();
+      (name, params.join(" "), "")
+    }
+    value => return Err(MalError::wrong_type("function", value)),
+  };
+  println!("-------------------------");
+  println!("{}", name);
+  println!("([{}])", params);
+  for line in doc.lines() {
+    println!("  {}", line.trim());
+  }
+  Ok(MalType::Nil)
 }
 
 // ============================================================================
 // Utilities
 // ============================================================================
-fn eval(mut args: &mut Vec<MalType>, env: &Env) -> MalResult {
-  if let Some(MalFunc { func, .. }) = env
-    .get("eval")
-    .expect("eval not a function")
-    .function_value()
-  {
-    func(&mut args, Some(env.clone()))
-  } else {
-    Err(MalError::generic("Not a function"))
+// Makes the tail call a native function returned, and any it leads to
+pub fn trampoline(mut value: MalType) -> MalResult {
+  while let MalType::TailCall(call) = value {
+    let TailCall {
+      func,
+      mut args,
+      then,
+    } = *call;
+    value = match func {
+      MalType::Function(func) => func.call_tail(&mut args)?,
+      func => eval_func(func, &mut args)?,
+    };
+    if let Some(then) = then {
+      value = then(trampoline(value)?)?;
+    }
   }
+  Ok(value)
 }
 
 pub fn eval_func(func: MalType, args: &mut Vec<MalType>) -> MalResult {
+  limits::tick()?;
   match func {
-    MalType::Function(MalFunc { func, env, .. }) => func(args, env),
+    MalType::Function(func) => func.call(args),
     MalType::Lambda(MalLambda {
       env,
       args: binds,
       body,
+      name,
+      code,
       ..
     }) => {
-      let binds: Vec<String> = binds
+      let binds: Vec<Symbol> = binds
         .into_iter()
         .filter_map(|val| val.symbol_value())
         .collect();
-      let inner_env = Env::new_with_bindings(Some(env), binds, args.clone());
-      eval(&mut body.clone(), &inner_env)
+      let fn_name = name.as_deref().unwrap_or("fn*");
+      let mut inner_env = Env::new_with_bindings(Some(env), fn_name, binds, args.clone().into())?;
+      match code {
+        Some(code) => vm::run(code, inner_env),
+        None => interpreter::eval(body[0].clone(), &mut inner_env),
+      }
     }
     _ => Err(MalError::wrong_arguments("Not a function")),
   }
 }
 
-fn expected_arguments(args: &mut Vec<MalType>, expected: usize) -> Result<(), MalError> {
-  if args.len() < expected {
-    let msg = format!("Wrong number of args, expected {}", expected);
-    Err(MalError::wrong_arguments(&msg))
-  } else {
-    Ok(())
-  }
-}
-
-fn to_numbers(args: &mut Vec<MalType>) -> Result<Vec<i64>, MalError> {
-  let mut results = Vec::new();
-  for i in args {
-    if let Some(val) = i.number_value() {
-      results.push(val);
-    } else {
-      return Err(MalError::not_a_number());
-    }
-  }
-  Ok(results)
-}
-
-fn get_number(arg: &MalType) -> Result<i64, MalError> {
-  match arg.number_value() {
-    Some(i) => Ok(i),
-    None => Err(MalError::not_a_number()),
-  }
-}
-
 fn values_equal(first: &MalType, second: &MalType) -> bool {
   use MalType::*;
   if first.is_list_or_vector() && second.is_list_or_vector() {
@@ -319,52 +742,95 @@ fn values_equal(first: &MalType, second: &MalType) -> bool {
       (Nil, Nil) => true,
       (True, True) => true,
       (False, False) => true,
-      (Symbol(a), Symbol(b)) => a == b,
+      (Symbol(a, _), Symbol(b, _)) => a == b,
       (Number(a), Number(b)) => a == b,
+      (BigInt(a), BigInt(b)) => a == b,
+      (Ratio(a), Ratio(b)) => a == b,
+      (Float(a), Float(b)) => a == b,
       (String(a), String(b)) => a == b,
       (Keyword(a), Keyword(b)) => a == b,
-      (List(a), List(b)) => list_equal(a, b),
-      (Vector(a), Vector(b)) => list_equal(a, b),
-      (HashMap(a), HashMap(b)) => list_equal(a, b),
+      (List(a, _, _), List(b, _, _)) => list_equal(a, b),
+      (Vector(a, _, _), Vector(b, _, _)) => list_equal(a, b),
+      (HashMap(a, _), HashMap(b, _)) => hash_map_equal(a, b),
       _ => false,
     }
   }
 }
 
-fn list_equal(first: &Vec<MalType>, second: &Vec<MalType>) -> bool {
-  if first.len() != second.len() {
-    return false;
+fn list_equal(first: &MalList, second: &MalList) -> bool {
+  first.len() == second.len()
+    && first
+      .iter()
+      .zip(second.iter())
+      .all(|(a, b)| values_equal(a, b))
+}
+
+fn hash_map_equal(first: &MalHashMap, second: &MalHashMap) -> bool {
+  first.len() == second.len()
+    && first.iter().all(|(key, value)| match second.get(key) {
+      Some(other) => values_equal(value, other),
+      None => false,
+    })
+}
+
+fn join(args: &[MalType], separator: &str, readable: bool) -> Result<String, MalError> {
+  let mut joined = String::new();
+  for (index, val) in args.iter().enumerate() {
+    if index > 0 {
+      limits::allocate(separator.len())?;
+      joined.push_str(separator);
+    }
+    joined.push_str(&printer::print_str_limited(val, readable)?);
   }
-  let mut result = true;
-  for (i, _) in first.iter().enumerate() {
-    if !values_equal(&first[i], &second[i]) {
-      result = false
+  Ok(joined)
+}
+
+// Counts the contents of the file at path before it is read
+// Reads the file at `path`, counting its contents against the memory limit.
+// Devices, pipes and procfs files have no useful size up front, so at most a
+// byte more than what is left is read to find out whether the file fits.
+fn read_allocated(path: &str) -> Result<String, MalError> {
+  let io_error = |err: io::Error| MalError::generic(&err.to_string());
+  let mut file = fs::File::open(path).map_err(io_error)?;
+  let mut contents = vec![];
+  match limits::remaining_memory() {
+    Some(remaining) => {
+      let limit = (remaining as u64).saturating_add(1);
+      file
+        .take(limit)
+        .read_to_end(&mut contents)
+        .map_err(io_error)?;
+      limits::allocate(contents.len())?;
+    }
+    None => {
+      file.read_to_end(&mut contents).map_err(io_error)?;
     }
   }
-  result
+  String::from_utf8(contents).map_err(|err| MalError::generic(&err.to_string()))
 }
 
-fn join(args: &mut Vec<MalType>, separator: &str, readable: bool) -> String {
-  args
-    .iter()
-    .map(|val| printer::print_str(val, readable))
-    .collect::<Vec<String>>()
-    .join(separator)
-}
-
-fn print(args: &mut Vec<MalType>, readable: bool) -> MalResult {
-  let joined = join(args, " ", readable);
+fn print(args: &[MalType], readable: bool) -> MalResult {
+  let joined = join(args, " ", readable)?;
   println!("{}", joined);
   Ok(MalType::Nil)
 }
 
-fn vec_value(arg: &MalType) -> Result<Vec<MalType>, MalError> {
-  if let Some(v) = arg.list_value() {
-    Ok(v.clone())
-  } else {
-    Err(MalError::wrong_arguments(&format!(
-      "Expected a list or vector but got {:?}",
-      arg
-    )))
+fn hash_key(arg: &MalType) -> Result<MalHashKey, MalError> {
+  match arg.to_hash_key() {
+    Some(key) => Ok(key),
+    None => Err(MalError::invalid_hash_key()),
+  }
+}
+
+fn assoc_pairs(mut map: MalHashMap, pairs: &[MalType]) -> Result<MalHashMap, MalError> {
+  if !pairs.len().is_multiple_of(2) {
+    return Err(MalError::wrong_arguments(
+      "Expected an even number of keys and values",
+    ));
+  }
+  limits::allocate_entries(pairs.len() / 2)?;
+  for pair in pairs.chunks(2) {
+    map.insert(hash_key(&pair[0])?, pair[1].to_owned());
   }
+  Ok(map)
 }
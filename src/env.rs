@@ -2,64 +2,162 @@ use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 
+use crate::convert::{typed_function, TypedFunction};
+use crate::symbol;
 use crate::types::*;
 
+// A frame holds the locals bound by fn*, let* or catch* in slots, which the
+// analyzer resolves references to by (depth, slot). Globals and anything
+// defined with def! at run time live in `data` and are looked up by name.
 #[derive(Clone)]
 struct EnvType {
-  pub data: HashMap<String, MalType>,
+  pub data: HashMap<Symbol, MalType>,
+  pub names: Vec<Symbol>,
+  pub slots: Vec<MalType>,
   pub parent: Option<Env>,
 }
 
+impl EnvType {
+  // Only slots already filled are visible, so a let* binding can't see later ones
+  fn slot_of(&self, key: Symbol) -> Option<usize> {
+    self.names[..self.slots.len()]
+      .iter()
+      .rposition(|name| *name == key)
+  }
+}
+
 #[derive(Clone)]
 pub struct Env(Rc<RefCell<EnvType>>);
 
 impl Env {
   pub fn new(parent: Option<Env>) -> Env {
+    Env::new_frame(parent, vec![])
+  }
+
+  // Frame for the given local names, whose slots are filled in order with push_slot
+  pub fn new_frame(parent: Option<Env>, names: Vec<Symbol>) -> Env {
     Env(Rc::new(RefCell::new(EnvType {
       data: HashMap::new(),
-      parent: parent.map(|p| p.clone()),
+      slots: Vec::with_capacity(names.len()),
+      names,
+      parent,
     })))
   }
 
+  // Binds parameters to arguments, erroring when their counts don't match.
+  // `name` identifies the function in the error message.
   pub fn new_with_bindings(
     parent: Option<Env>,
-    binds: Vec<String>,
-    mut exprs: Vec<MalType>,
-  ) -> Env {
-    let mut env = Env::new(parent);
-    let mut is_more = false;
-    for bind in binds {
-      if bind == "&" {
-        is_more = true;
-      } else if is_more {
-        env.set(&bind, MalType::List(exprs));
-        break;
-      } else if exprs.len() > 0 {
-        env.set(&bind, exprs.remove(0));
+    name: &str,
+    binds: Vec<Symbol>,
+    mut exprs: MalList,
+  ) -> Result<Env, MalError> {
+    let fixed = binds.iter().position(|bind| *bind == symbol::AMPERSAND);
+    let arity = match fixed {
+      Some(fixed) => Arity::at_least(fixed),
+      None => Arity::exact(binds.len()),
+    };
+    if !arity.accepts(exprs.len()) {
+      return Err(MalError::wrong_arity(name, exprs.len(), arity));
+    }
+    let names = binds
+      .into_iter()
+      .filter(|bind| *bind != symbol::AMPERSAND)
+      .collect();
+    let env = Env::new_frame(parent, names);
+    {
+      let mut frame = env.0.borrow_mut();
+      let count = fixed.unwrap_or(frame.names.len());
+      for _ in 0..count {
+        let value = exprs.pop_front().unwrap();
+        frame.slots.push(value);
+      }
+      if fixed.is_some() && frame.slots.len() < frame.names.len() {
+        frame.slots.push(MalType::List(exprs, None, None));
       }
     }
-    env
+    Ok(env)
   }
 
-  pub fn set(&mut self, key: &str, value: MalType) {
-    self.0.borrow_mut().data.insert(key.to_string(), value);
+  pub fn parent(&self) -> Option<Env> {
+    self.0.borrow().parent.clone()
+  }
+
+  pub fn push_slot(&mut self, value: MalType) {
+    self.0.borrow_mut().slots.push(value);
+  }
+
+  pub fn get_local(&self, depth: usize, slot: usize) -> MalType {
+    let frame = self.0.borrow();
+    if depth == 0 {
+      frame.slots[slot].to_owned()
+    } else {
+      frame.parent.as_ref().unwrap().get_local(depth - 1, slot)
+    }
   }
 
-  pub fn find(&self, key: &str) -> Option<Env> {
-    if self.0.borrow_mut().data.contains_key(key) {
+  // Names visible in each frame's slots, from the outermost frame inwards
+  pub fn scopes(&self) -> Vec<Vec<Symbol>> {
+    let frame = self.0.borrow();
+    let mut scopes = match &frame.parent {
+      Some(parent) => parent.scopes(),
+      None => vec![],
+    };
+    scopes.push(frame.names[..frame.slots.len()].to_vec());
+    scopes
+  }
+
+  pub fn set<K: Into<Symbol>>(&mut self, key: K, value: MalType) {
+    let key = key.into();
+    let mut frame = self.0.borrow_mut();
+    match frame.slot_of(key) {
+      Some(slot) => frame.slots[slot] = value,
+      None => {
+        frame.data.insert(key, value);
+      }
+    }
+  }
+
+  // Binds a native function, which may be a closure over host state
+  pub fn set_native<F>(&mut self, name: &str, arity: Arity, func: F)
+  where
+    F: Fn(&mut Vec<MalType>) -> MalResult + 'static,
+  {
+    self.set(name, MalType::Function(MalFunc::new(name, arity, func)));
+  }
+
+  // Binds a typed Rust function, converting its arguments and result automatically
+  pub fn set_typed<Args, F>(&mut self, name: &str, func: F)
+  where
+    F: TypedFunction<Args> + 'static,
+  {
+    self.set(name, MalType::Function(typed_function(name, func)));
+  }
+
+  pub fn find(&self, key: Symbol) -> Option<Env> {
+    let frame = self.0.borrow();
+    if frame.slot_of(key).is_some() || frame.data.contains_key(&key) {
       Some(self.clone())
     } else {
-      match &self.0.borrow().parent {
+      match &frame.parent {
         Some(env) => env.find(key),
         None => None,
       }
     }
   }
 
-  pub fn get(&self, key: &str) -> MalResult {
-    match self.find(key) {
-      Some(env) => Ok(env.0.borrow().data.get(key).unwrap().to_owned()),
-      None => Err(MalError::symbol_not_found(key)),
+  pub fn get<K: Into<Symbol>>(&self, key: K) -> MalResult {
+    let key = key.into();
+    let frame = self.0.borrow();
+    if let Some(slot) = frame.slot_of(key) {
+      return Ok(frame.slots[slot].to_owned());
+    }
+    if let Some(value) = frame.data.get(&key) {
+      return Ok(value.to_owned());
+    }
+    match &frame.parent {
+      Some(env) => env.get(key),
+      None => Err(MalError::symbol_not_found(&key.name())),
     }
   }
 }
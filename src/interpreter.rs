@@ -0,0 +1,585 @@
+use crate::analyzer;
+use crate::capabilities::Capabilities;
+use crate::env::Env;
+use crate::limits::{self, Depth, Limits};
+use crate::reader;
+use crate::symbol;
+use crate::types::*;
+use crate::vm;
+use im_rc::vector;
+use std::fs;
+use std::mem;
+use std::rc::Rc;
+
+// Embeddable MAL evaluator. Holds the global environment with the core
+// functions and the mal-defined prelude already loaded.
+pub struct Interpreter {
+  env: Env,
+  engine: Engine,
+  limits: Limits,
+}
+
+// How forms are evaluated. Both engines share MalType and the core functions,
+// and lambdas created by one can be called by the other.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Engine {
+  TreeWalker,
+  Vm,
+}
+
+impl Engine {
+  pub fn eval(self, form: MalType, env: &mut Env) -> MalResult {
+    match self {
+      Engine::TreeWalker => eval(form, env),
+      Engine::Vm => vm::eval(form, env),
+    }
+  }
+}
+
+impl Interpreter {
+  pub fn new() -> Interpreter {
+    Interpreter::with_engine(Engine::TreeWalker)
+  }
+
+  pub fn with_engine(engine: Engine) -> Interpreter {
+    Interpreter::with_capabilities(engine, Capabilities::Full)
+  }
+
+  // Interpreter whose scripts can only use the core functions `capabilities`
+  // allow. load-file goes through read-file, so it is limited the same way.
+  pub fn with_capabilities(engine: Engine, capabilities: Capabilities) -> Interpreter {
+    let mut env = Env::new(None);
+    capabilities.install(&mut env);
+    let global = env.clone();
+    env.set_native("eval", Arity::exact(1), move |args| {
+      eval_fn(args, &global, engine)
+    });
+    env.set("*ARGV*", MalType::List(MalList::new(), None, None));
+    env.set("*host-language*", MalType::String("rust".to_string()));
+
+    let mut interpreter = Interpreter {
+      env,
+      engine,
+      limits: Limits::default(),
+    };
+    let prelude = reader::read_file(PRELUDE.join("\n"), "<prelude>")
+      .and_then(|form| interpreter.eval_form(form));
+    prelude.expect("Failed to evaluate prelude");
+    interpreter
+  }
+
+  pub fn eval_str(&mut self, input: &str) -> MalResult {
+    let form = reader::read_str(input.to_string())?;
+    self.eval_form(form)
+  }
+
+  pub fn eval_form(&mut self, form: MalType) -> MalResult {
+    let Interpreter {
+      env,
+      engine,
+      limits,
+    } = self;
+    limits::with_limits(*limits, || engine.eval(form, env))
+  }
+
+  pub fn limits(&self) -> Limits {
+    self.limits
+  }
+
+  // Applies `limits` to later evaluations
+  pub fn set_limits(&mut self, limits: Limits) {
+    self.limits = limits;
+  }
+
+  pub fn load_file(&mut self, path: &str) -> MalResult {
+    let source = fs::read_to_string(path).map_err(|err| MalError::generic(&err.to_string()))?;
+    let form = reader::read_file(source, path)?;
+    self.eval_form(form)
+  }
+
+  // Shares the global environment, so values set on it are visible to later evaluations
+  pub fn global_env(&self) -> Env {
+    self.env.clone()
+  }
+}
+
+impl Default for Interpreter {
+  fn default() -> Interpreter {
+    Interpreter::new()
+  }
+}
+
+const PRELUDE: &[&str] = &[
+  "(def! not (fn* (a) (if a false true)))",
+  "(def! load-file (fn* (f) (eval (read-file f))))",
+  "(defmacro! cond (fn* (& xs) (if (> (count xs) 0) (list 'if (first xs) (if (> (count xs) 1) (nth xs 1) (throw \"odd number of forms to cond\")) (cons 'cond (rest (rest xs)))))))",
+];
+
+fn eval_hash_map(map: MalHashMap, env: &mut Env) -> Result<MalHashMap, MalError> {
+  let mut new_map = MalHashMap::new();
+  for (key, value) in map {
+    new_map.insert(key, eval(value, env)?);
+  }
+  Ok(new_map)
+}
+
+fn def(env: &mut Env, list: MalList) -> MalResult {
+  let key = match list.get(0) {
+    Some(k) => k
+      .symbol_value()
+      .ok_or_else(|| MalError::wrong_arguments("Expected symbol"))?,
+    None => return Err(MalError::unknown()),
+  };
+  let value = match list.get(1) {
+    Some(k) => eval(k.to_owned(), env)?.with_name(&key.name()),
+    None => return Err(MalError::unknown()),
+  };
+  env.set(key, value.clone());
+  Ok(value)
+}
+
+fn defmacro(env: &mut Env, list: MalList) -> MalResult {
+  let key = match list.get(0) {
+    Some(k) => k
+      .symbol_value()
+      .ok_or_else(|| MalError::wrong_arguments("Expected symbol"))?,
+    None => return Err(MalError::unknown()),
+  };
+  let value = match list.get(1) {
+    Some(k) => eval(k.to_owned(), env)?.with_name(&key.name()),
+    None => return Err(MalError::unknown()),
+  };
+  match value {
+    MalType::Lambda(lambda) => {
+      let value = MalType::Lambda(MalLambda {
+        is_macro: true,
+        ..lambda
+      });
+      env.set(key, value.clone());
+      Ok(value)
+    }
+    _ => Err(MalError::wrong_arguments("Expected fn* as macro body")),
+  }
+}
+
+fn let_star_env(env: &mut Env, list: &MalList) -> Result<Env, MalError> {
+  let bindings = match list.get(0) {
+    Some(MalType::List(list, _, _)) | Some(MalType::Vector(list, _, _)) => list.clone(),
+    _ => return Err(MalError::wrong_arguments("Expected list or vector")),
+  };
+
+  if bindings.len() % 2 != 0 {
+    return Err(MalError::unknown());
+  }
+
+  let names = bindings
+    .iter()
+    .step_by(2)
+    .map(|name| {
+      name
+        .symbol_value()
+        .ok_or_else(|| MalError::wrong_arguments("Expected symbol"))
+    })
+    .collect::<Result<Vec<Symbol>, MalError>>()?;
+  let mut new_env = Env::new_frame(Some(env.to_owned()), names);
+  for value in bindings.into_iter().skip(1).step_by(2) {
+    let value = eval(value, &mut new_env)?;
+    new_env.push_slot(value);
+  }
+  Ok(new_env)
+}
+
+fn eval_do(input: &MalList, env: &mut Env) -> MalResult {
+  let mut list = input.clone();
+  let last = list.pop_back().ok_or(MalError::unknown())?;
+  eval_ast(MalType::List(list, None, None), env)?;
+  Ok(last)
+}
+
+fn eval_if(input: &MalList, env: &mut Env) -> MalResult {
+  if input.len() < 2 {
+    return Err(MalError::unknown());
+  }
+
+  let condition = eval(input[0].to_owned(), env)?;
+  let result = if condition.is_truthy() {
+    input[1].to_owned()
+  } else {
+    match input.get(2) {
+      Some(falsey) => falsey.to_owned(),
+      None => MalType::Nil,
+    }
+  };
+  Ok(result)
+}
+
+fn eval_fn_star(input: &MalList, env: &mut Env) -> MalResult {
+  if input.len() < 2 {
+    return Err(MalError::generic("Not enough args to fn*, expecting 2"));
+  }
+  let args = &input[0];
+  if let Some(args) = args.list_value() {
+    let body = input[1].clone();
+    let lambda = MalType::Lambda(MalLambda {
+      env: env.clone(),
+      args,
+      body: vec![body],
+      is_macro: false,
+      meta: Box::new(MalType::Nil),
+      name: None,
+      code: None,
+    });
+    Ok(lambda)
+  } else {
+    Err(MalError::generic(&format!(
+      "Expecting vector as first argument of fn*, but got: {:?}",
+      args
+    )))
+  }
+}
+
+fn eval_try(input: &MalList, env: &mut Env) -> MalResult {
+  let body = match input.get(0) {
+    Some(body) => body.to_owned(),
+    None => return Ok(MalType::Nil),
+  };
+  let result = eval(body, env);
+  let catch = match input.get(1) {
+    Some(catch) => catch.list_value().unwrap_or_default(),
+    None => return result,
+  };
+  if catch.len() < 3 || !catch[0].is_symbol_named(symbol::CATCH) {
+    return Err(MalError::wrong_arguments(
+      "Expected (catch* symbol body) as second argument of try*",
+    ));
+  }
+  match result {
+    Err(err) if err.is_catchable() => {
+      let binding = match catch[1].symbol_value() {
+        Some(sym) => sym,
+        None => return Err(MalError::wrong_arguments("Expected symbol in catch*")),
+      };
+      let mut catch_env = Env::new_with_bindings(
+        Some(env.clone()),
+        "catch*",
+        vec![binding],
+        vector![err.exception_value()],
+      )?;
+      catch_env.set("*backtrace*", err.backtrace_value());
+      eval(catch[2].to_owned(), &mut catch_env)
+    }
+    result => result,
+  }
+}
+
+fn eval_ast(input: MalType, env: &mut Env) -> MalResult {
+  let mut eval_list = |list: MalList| -> Result<MalList, MalError> {
+    list.into_iter().map(|v| eval(v, env)).collect()
+  };
+  let value = match input {
+    MalType::Symbol(sym, span) => env.get(sym).map_err(|err| err.with_span(span))?,
+    MalType::Local(_, depth, slot) => env.get_local(depth, slot),
+    MalType::List(list, _, _) => MalType::List(eval_list(list)?, None, None),
+    MalType::Vector(list, span, _) => {
+      let list = eval_list(list).map_err(|err| err.with_span(span))?;
+      MalType::Vector(list, None, None)
+    }
+    MalType::HashMap(map, _) => MalType::HashMap(eval_hash_map(map, env)?, None),
+    _ => input,
+  };
+  Ok(value)
+}
+
+pub fn eval(input: MalType, env: &mut Env) -> MalResult {
+  let _depth = Depth::enter()?;
+  let mut frame = None;
+  eval_in_frame(input, env, &mut frame).map_err(|err| match frame {
+    Some(frame) => err.with_frame(frame),
+    None => err,
+  })
+}
+
+// Evaluates with tail calls, recording the lambda currently running in `frame`
+fn eval_in_frame(input: MalType, env: &mut Env, frame: &mut Option<Frame>) -> MalResult {
+  let mut input = input.clone();
+  let mut env = env.clone();
+  // Continuations of the native functions whose tail calls are being made
+  let mut pending: Vec<Continuation> = vec![];
+  loop {
+    let mut value = loop {
+      limits::tick()?;
+      if !input.is_list() {
+        break eval_ast(mem::replace(&mut input, MalType::Nil), &mut env)?;
+      }
+      input = macroexpand(input, &mut env)?;
+      if !input.is_list() {
+        break eval_ast(mem::replace(&mut input, MalType::Nil), &mut env)?;
+      } else if input.list_value().unwrap().is_empty() {
+        break mem::replace(&mut input, MalType::Nil);
+      } else if is_special_form(&input) {
+        // Errors without a location of their own point at the special form
+        let span = input.span();
+        let located = |err: MalError| err.with_span(span.clone());
+        if let Some(mut list) = input.list_value() {
+          if let Some(MalType::Symbol(sym, _)) = list.pop_front() {
+            match sym {
+              symbol::DEF => {
+                break def(&mut env, list).map_err(located)?;
+              }
+              symbol::LET => {
+                env = let_star_env(&mut env, &list).map_err(located)?;
+                input = list
+                  .get(1)
+                  .cloned()
+                  .ok_or_else(MalError::unknown)
+                  .map_err(located)?;
+              }
+              symbol::DO => {
+                input = eval_do(&list, &mut env).map_err(located)?;
+              }
+              symbol::IF => {
+                input = eval_if(&list, &mut env).map_err(located)?;
+              }
+              symbol::FN => {
+                let list = analyzer::analyze_fn(list, &env);
+                break eval_fn_star(&list, &mut env).map_err(located)?;
+              }
+              symbol::FN_ANALYZED => {
+                break eval_fn_star(&list, &mut env).map_err(located)?;
+              }
+              symbol::QUOTE => {
+                if list.is_empty() {
+                  return Err(located(MalError::wrong_arguments(
+                    "Missing argument for quote",
+                  )));
+                }
+                break list.pop_front().unwrap();
+              }
+              symbol::QUASIQUOTE => {
+                input = match list.pop_front() {
+                  Some(ast) => quasiquote(ast).map_err(located)?,
+                  None => MalType::List(MalList::new(), None, None),
+                };
+              }
+              symbol::DEFMACRO => {
+                break defmacro(&mut env, list).map_err(located)?;
+              }
+              symbol::TRY => {
+                break eval_try(&list, &mut env).map_err(located)?;
+              }
+              symbol::MACROEXPAND => {
+                if list.is_empty() {
+                  return Err(located(MalError::wrong_arguments(
+                    "Missing argument for macroexpand",
+                  )));
+                }
+                break macroexpand(list.pop_front().unwrap(), &mut env).map_err(located)?;
+              }
+              _ => return Err(MalError::generic("Unknown special symbol")),
+            };
+          } else {
+            panic!("No longer a list somehow");
+          }
+        } else {
+          panic!("No longer a list somehow");
+        }
+      } else {
+        // Must be a function or lambda call
+        let span = input.span();
+        let mut list = eval_ast(mem::replace(&mut input, MalType::Nil), &mut env)
+          .map_err(|err| err.with_span(span.clone()))?
+          .list_value()
+          .unwrap();
+        let call = TailCall {
+          func: list.pop_front().unwrap(),
+          args: list.into_iter().collect(),
+          then: None,
+        };
+        if let Some(value) = tail_call(call, span, &mut env, &mut input, frame, &mut pending)? {
+          break value;
+        }
+      }
+    };
+    // The pending continuations turn the value into the result
+    loop {
+      let then = match pending.pop() {
+        Some(then) => then,
+        None => return Ok(value),
+      };
+      value = match then(value)? {
+        MalType::TailCall(call) => {
+          match tail_call(*call, None, &mut env, &mut input, frame, &mut pending)? {
+            Some(value) => value,
+            None => break,
+          }
+        }
+        value => value,
+      };
+    }
+  }
+}
+
+// Makes `call` from the frame being evaluated. A lambda's body replaces the
+// input, returning None, while a native function's result is returned once
+// any tail call it ends with has been made.
+fn tail_call(
+  mut call: TailCall,
+  span: Option<Rc<Span>>,
+  env: &mut Env,
+  input: &mut MalType,
+  frame: &mut Option<Frame>,
+  pending: &mut Vec<Continuation>,
+) -> Result<Option<MalType>, MalError> {
+  loop {
+    if let Some(then) = call.then.take() {
+      pending.push(then);
+    }
+    match call.func {
+      MalType::Function(func) => {
+        match func
+          .call_tail(&mut call.args)
+          .map_err(|err| err.with_span(span.clone()))?
+        {
+          MalType::TailCall(next) => call = *next,
+          value => return Ok(Some(value)),
+        }
+      }
+      MalType::Lambda(MalLambda {
+        env: l_env,
+        args,
+        body,
+        name,
+        ..
+      }) => {
+        let binds: Vec<Symbol> = args
+          .into_iter()
+          .filter_map(|val| val.symbol_value())
+          .collect();
+        let fn_name = name.as_deref().unwrap_or("fn*");
+        *env = Env::new_with_bindings(Some(l_env), fn_name, binds, call.args.into())
+          .map_err(|err| err.with_span(span.clone()))?;
+        *input = body.first().unwrap().clone();
+        // A tail call replaces the caller's frame
+        *frame = Some(Frame { name, span });
+        return Ok(None);
+      }
+      _ => return Err(MalError::not_a_function().with_span(span)),
+    }
+  }
+}
+
+fn is_macro_call(input: &MalType, env: &Env) -> bool {
+  if let Some(list) = input.list_value() {
+    if let Some(MalType::Symbol(sym, _)) = list.front() {
+      if let Ok(value) = env.get(*sym) {
+        return value.is_macro();
+      }
+    }
+  }
+  false
+}
+
+fn macroexpand(input: MalType, env: &mut Env) -> MalResult {
+  let mut input = input;
+  while is_macro_call(&input, env) {
+    let mut list = input.list_value().unwrap();
+    let sym = list.pop_front().unwrap().symbol_value().unwrap();
+    // The call may be in a fn* analyzed before the macro was defined
+    let list = list.into_iter().map(analyzer::unresolve).collect();
+    let MalLambda {
+      env: l_env,
+      args,
+      body,
+      ..
+    } = env.get(sym)?.lambda_value().unwrap();
+    let binds: Vec<Symbol> = args
+      .into_iter()
+      .filter_map(|val| val.symbol_value())
+      .collect();
+    let mut macro_env = Env::new_with_bindings(Some(l_env), &sym.name(), binds, list)?;
+    input = eval(body.first().unwrap().clone(), &mut macro_env)?;
+  }
+  Ok(input)
+}
+
+pub(crate) fn quasiquote(ast: MalType) -> MalResult {
+  let expansion = if !ast.is_pair() {
+    MalType::List(
+      vector![MalType::Symbol(symbol::QUOTE, None), ast],
+      None,
+      None,
+    )
+  } else if first(&ast).is_symbol_named(symbol::UNQUOTE) {
+    unquoted(&ast, "unquote")?
+  } else if first(&ast).is_pair() && first(&first(&ast)).is_symbol_named(symbol::SPLICE_UNQUOTE) {
+    let ret_list = vector![
+      MalType::Symbol(symbol::CONCAT, None),
+      unquoted(&first(&ast), "splice-unquote")?,
+      quasiquote(rest(&ast))?,
+    ];
+    MalType::List(ret_list, None, None)
+  } else {
+    let ret_list = vector![
+      MalType::Symbol(symbol::CONS, None),
+      quasiquote(first(&ast))?,
+      quasiquote(rest(&ast))?,
+    ];
+    MalType::List(ret_list, None, None)
+  };
+  Ok(expansion)
+}
+
+// The form an unquote or splice-unquote is given
+fn unquoted(form: &MalType, name: &str) -> MalResult {
+  let list = form.list_value().unwrap_or_default();
+  list
+    .get(1)
+    .cloned()
+    .ok_or_else(|| MalError::wrong_arguments(&format!("Missing argument for {}", name)))
+}
+
+// first and rest are only taken of pairs
+fn first(val: &MalType) -> MalType {
+  let list = val.list_value().expect("Requires a list");
+  list.front().expect("Requires a non-empty list").clone()
+}
+
+fn rest(val: &MalType) -> MalType {
+  let list = val.list_value().expect("Requires a list");
+  assert!(!list.is_empty());
+  MalType::List(list.skip(1), None, None)
+}
+
+fn is_special_form(input: &MalType) -> bool {
+  if let Some(list) = input.list_value() {
+    if list.is_empty() {
+      return false;
+    }
+    if let Some(sym) = list[0].symbol_value() {
+      return matches!(
+        sym,
+        symbol::DEF
+          | symbol::LET
+          | symbol::DO
+          | symbol::IF
+          | symbol::FN
+          | symbol::FN_ANALYZED
+          | symbol::QUOTE
+          | symbol::QUASIQUOTE
+          | symbol::DEFMACRO
+          | symbol::MACROEXPAND
+          | symbol::TRY
+      );
+    }
+  }
+  false
+}
+
+#[allow(clippy::ptr_arg)]
+fn eval_fn(args: &mut Vec<MalType>, env: &Env, engine: Engine) -> MalResult {
+  if let Some(arg) = args.first() {
+    engine.eval(arg.to_owned(), &mut env.clone())
+  } else {
+    Err(MalError::generic("Not enough arguments"))
+  }
+}
@@ -1,8 +1,17 @@
 #[macro_use]
 extern crate lazy_static;
 
+pub mod analyzer;
+pub mod capabilities;
+pub mod compiler;
+pub mod convert;
 pub mod core;
 pub mod env;
+pub mod interpreter;
+pub mod limits;
+pub mod number;
 pub mod printer;
 pub mod reader;
+pub mod symbol;
 pub mod types;
+pub mod vm;
@@ -0,0 +1,219 @@
+use std::cell::Cell;
+use std::hint;
+use std::mem;
+use std::panic;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::types::{MalError, MalHashKey, MalType};
+
+// Bounds on evaluation, which an Interpreter applies to each of its calls
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+  // Deepest nesting of evaluations and non-tail calls before StackOverflow is
+  // raised. On a thread with a normal stack, max_stack usually stops it first.
+  pub max_depth: usize,
+  // Bytes of Rust stack evaluation may use, from where the outermost
+  // evaluation started, before StackOverflow is raised. The default fits the
+  // 2 MiB a spawned thread gets; see with_stack_size for a bigger stack.
+  pub max_stack: usize,
+  // Steps an evaluation may take, counting each pass of the evaluator's loop
+  // and each function call, or None for no limit. Like the timeout and memory
+  // limit, running out can't be caught by catch*.
+  pub fuel: Option<u64>,
+  // Time an evaluation may run for, or None for no limit
+  pub timeout: Option<Duration>,
+  // Approximate bytes of strings and collections an evaluation may build, or
+  // None for no limit. Values dropped during the evaluation don't give any back.
+  pub max_memory: Option<usize>,
+}
+
+const DEFAULT_MAX_DEPTH: usize = 10_000;
+
+// Stack of a thread from std::thread::spawn, the smallest an Interpreter is
+// likely to run on. Evaluation leaves a quarter of it for the host and for
+// what runs between two checks.
+const THREAD_STACK_SIZE: usize = 2 * 1024 * 1024;
+
+// Stack that fits evaluating to the default depth, even in a debug build, when
+// given to Limits::with_stack_size
+pub const STACK_SIZE: usize = 512 * 1024 * 1024;
+
+impl Default for Limits {
+  fn default() -> Limits {
+    Limits {
+      max_depth: DEFAULT_MAX_DEPTH,
+      max_stack: THREAD_STACK_SIZE / 4 * 3,
+      fuel: None,
+      timeout: None,
+      max_memory: None,
+    }
+  }
+}
+
+impl Limits {
+  // Default limits for evaluating on a thread with `stack_size` bytes of stack
+  pub fn with_stack_size(stack_size: usize) -> Limits {
+    Limits {
+      max_stack: stack_size / 4 * 3,
+      ..Limits::default()
+    }
+  }
+}
+
+thread_local! {
+  static MAX_DEPTH: Cell<usize> = const { Cell::new(DEFAULT_MAX_DEPTH) };
+  static DEPTH: Cell<usize> = const { Cell::new(0) };
+  static MAX_STACK: Cell<usize> = const { Cell::new(THREAD_STACK_SIZE / 4 * 3) };
+  // Address on the stack where the outermost evaluation started
+  static STACK_BASE: Cell<usize> = const { Cell::new(0) };
+  static FUEL: Cell<Option<u64>> = const { Cell::new(None) };
+  static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+  static MEMORY: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+// Applies `limits` to evaluations on this thread while `f` runs. The fuel and
+// timeout, like the memory limit, are a budget for this call of with_limits as a whole.
+pub fn with_limits<T, F>(limits: Limits, f: F) -> T
+where
+  F: FnOnce() -> T,
+{
+  let deadline = limits.timeout.map(|timeout| Instant::now() + timeout);
+  let _restore = Restore {
+    max_depth: MAX_DEPTH.with(|max| max.replace(limits.max_depth)),
+    max_stack: MAX_STACK.with(|max| max.replace(limits.max_stack)),
+    fuel: FUEL.with(|fuel| fuel.replace(limits.fuel)),
+    deadline: DEADLINE.with(|cell| cell.replace(deadline)),
+    memory: MEMORY.with(|memory| memory.replace(limits.max_memory)),
+  };
+  f()
+}
+
+// Puts back the limits in place before with_limits, even when `f` panics
+struct Restore {
+  max_depth: usize,
+  max_stack: usize,
+  fuel: Option<u64>,
+  deadline: Option<Instant>,
+  memory: Option<usize>,
+}
+
+impl Drop for Restore {
+  fn drop(&mut self) {
+    MAX_DEPTH.with(|max| max.set(self.max_depth));
+    MAX_STACK.with(|max| max.set(self.max_stack));
+    FUEL.with(|fuel| fuel.set(self.fuel));
+    DEADLINE.with(|deadline| deadline.set(self.deadline));
+    MEMORY.with(|memory| memory.set(self.memory));
+  }
+}
+
+// Spends a step of the budget, failing once the fuel has run out or the
+// deadline has passed. Evaluation can't loop without calling this.
+pub fn tick() -> Result<(), MalError> {
+  if let Some(fuel) = FUEL.with(Cell::get) {
+    if fuel == 0 {
+      return Err(MalError::budget_exceeded("out of fuel"));
+    }
+    FUEL.with(|cell| cell.set(Some(fuel - 1)));
+  }
+  if let Some(deadline) = DEADLINE.with(Cell::get) {
+    if Instant::now() >= deadline {
+      return Err(MalError::budget_exceeded("timed out"));
+    }
+  }
+  Ok(())
+}
+
+// Counts `bytes` of a new string or collection against the memory limit,
+// failing if they don't fit in what is left
+pub fn allocate(bytes: usize) -> Result<(), MalError> {
+  if let Some(memory) = MEMORY.with(Cell::get) {
+    if bytes > memory {
+      return Err(MalError::memory_limit_exceeded());
+    }
+    MEMORY.with(|cell| cell.set(Some(memory - bytes)));
+  }
+  Ok(())
+}
+
+// Bytes left under the memory limit, if there is one
+pub fn remaining_memory() -> Option<usize> {
+  MEMORY.with(Cell::get)
+}
+
+// Counts `count` new items of a list or vector
+pub fn allocate_items(count: usize) -> Result<(), MalError> {
+  allocate(count.saturating_mul(mem::size_of::<MalType>()))
+}
+
+// Counts copies of the values `values` returns. Cloning a string or keyword
+// copies its text, which is counted too, so they are only walked when there is
+// a limit.
+pub fn allocate_copies<'a, F, I>(values: F) -> Result<(), MalError>
+where
+  F: FnOnce() -> I,
+  I: IntoIterator<Item = &'a MalType>,
+{
+  if MEMORY.with(Cell::get).is_none() {
+    return Ok(());
+  }
+  let bytes = values().into_iter().fold(0usize, |bytes, value| {
+    let text = match value {
+      MalType::String(s) | MalType::Keyword(s) => s.len(),
+      _ => 0,
+    };
+    bytes.saturating_add(mem::size_of::<MalType>() + text)
+  });
+  allocate(bytes)
+}
+
+// Counts `count` new entries of a hash-map
+pub fn allocate_entries(count: usize) -> Result<(), MalError> {
+  allocate(count.saturating_mul(mem::size_of::<(MalHashKey, MalType)>()))
+}
+
+// A level of evaluation, counted until it is dropped
+pub struct Depth(());
+
+impl Depth {
+  pub fn enter() -> Result<Depth, MalError> {
+    let max = MAX_DEPTH.with(Cell::get);
+    let marker = 0u8;
+    let here = hint::black_box(&marker) as *const u8 as usize;
+    DEPTH.with(|depth| {
+      if depth.get() == 0 {
+        STACK_BASE.with(|base| base.set(here));
+      }
+      let used = STACK_BASE.with(Cell::get).abs_diff(here);
+      if depth.get() >= max || used > MAX_STACK.with(Cell::get) {
+        return Err(MalError::stack_overflow());
+      }
+      depth.set(depth.get() + 1);
+      Ok(Depth(()))
+    })
+  }
+}
+
+impl Drop for Depth {
+  fn drop(&mut self) {
+    DEPTH.with(|depth| depth.set(depth.get() - 1));
+  }
+}
+
+// Runs `f` on a new thread with `stack_size` bytes of stack and waits for it.
+// The Interpreter can't be sent between threads, so `f` has to create it.
+pub fn with_stack<T, F>(stack_size: usize, f: F) -> T
+where
+  F: FnOnce() -> T + Send + 'static,
+  T: Send + 'static,
+{
+  let thread = thread::Builder::new()
+    .stack_size(stack_size)
+    .spawn(f)
+    .expect("Failed to spawn evaluation thread");
+  match thread.join() {
+    Ok(value) => value,
+    Err(payload) => panic::resume_unwind(payload),
+  }
+}
@@ -0,0 +1,204 @@
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{FromPrimitive, ToPrimitive, Zero};
+use std::cmp::Ordering;
+
+use crate::types::*;
+
+// Numeric tower used by the arithmetic core functions. Integer operations that
+// overflow are promoted to BigInt, division of integers produces an exact Ratio,
+// and exact numbers are promoted to floats whenever the two operands differ.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Number {
+  Int(i64),
+  BigInt(BigInt),
+  Ratio(BigRational),
+  Float(f64),
+}
+
+impl Number {
+  pub fn from_mal(value: &MalType) -> Option<Number> {
+    match value {
+      MalType::Number(n) => Some(Number::Int(*n)),
+      MalType::BigInt(n) => Some(Number::BigInt(n.to_owned())),
+      MalType::Ratio(n) => Some(Number::Ratio(n.to_owned())),
+      MalType::Float(f) => Some(Number::Float(*f)),
+      _ => None,
+    }
+  }
+
+  pub fn to_mal(self) -> MalType {
+    match self {
+      Number::Int(n) => MalType::Number(n),
+      Number::BigInt(n) => MalType::BigInt(n),
+      Number::Ratio(n) => MalType::Ratio(n),
+      Number::Float(f) => MalType::Float(f),
+    }
+  }
+
+  // Big integers that fit back into an i64 are demoted so results stay small
+  pub fn from_bigint(n: BigInt) -> Number {
+    match n.to_i64() {
+      Some(n) => Number::Int(n),
+      None => Number::BigInt(n),
+    }
+  }
+
+  // Ratios with a denominator of one are demoted to integers
+  pub fn from_ratio(n: BigRational) -> Number {
+    if n.is_integer() {
+      Number::from_bigint(n.to_integer())
+    } else {
+      Number::Ratio(n)
+    }
+  }
+
+  pub fn to_f64(&self) -> f64 {
+    match self {
+      Number::Int(n) => *n as f64,
+      Number::BigInt(n) => n.to_f64().unwrap_or(f64::NAN),
+      Number::Ratio(n) => n.to_f64().unwrap_or(f64::NAN),
+      Number::Float(f) => *f,
+    }
+  }
+
+  fn to_bigint(&self) -> Option<BigInt> {
+    match self {
+      Number::Int(n) => Some(BigInt::from(*n)),
+      Number::BigInt(n) => Some(n.to_owned()),
+      _ => None,
+    }
+  }
+
+  fn to_ratio(&self) -> Option<BigRational> {
+    match self {
+      Number::Ratio(n) => Some(n.to_owned()),
+      Number::Float(_) => None,
+      n => n.to_bigint().map(BigRational::from_integer),
+    }
+  }
+
+  pub fn plus(self, other: Number) -> Number {
+    match (&self, &other) {
+      (Number::Int(a), Number::Int(b)) => match a.checked_add(*b) {
+        Some(n) => Number::Int(n),
+        None => Number::from_bigint(BigInt::from(*a) + b),
+      },
+      _ => self.promote(other, |a, b| a + b, |a, b| a + b, |a, b| a + b),
+    }
+  }
+
+  pub fn minus(self, other: Number) -> Number {
+    match (&self, &other) {
+      (Number::Int(a), Number::Int(b)) => match a.checked_sub(*b) {
+        Some(n) => Number::Int(n),
+        None => Number::from_bigint(BigInt::from(*a) - b),
+      },
+      _ => self.promote(other, |a, b| a - b, |a, b| a - b, |a, b| a - b),
+    }
+  }
+
+  pub fn multiply(self, other: Number) -> Number {
+    match (&self, &other) {
+      (Number::Int(a), Number::Int(b)) => match a.checked_mul(*b) {
+        Some(n) => Number::Int(n),
+        None => Number::from_bigint(BigInt::from(*a) * b),
+      },
+      _ => self.promote(other, |a, b| a * b, |a, b| a * b, |a, b| a * b),
+    }
+  }
+
+  // Exact division by zero is an error, float division follows IEEE 754
+  pub fn divide(self, other: Number) -> Result<Number, MalError> {
+    if let (Number::Int(a), Number::Int(b)) = (&self, &other) {
+      if let (Some(0), Some(n)) = (a.checked_rem(*b), a.checked_div(*b)) {
+        return Ok(Number::Int(n));
+      }
+    }
+    let result = match (self.to_ratio(), other.to_ratio()) {
+      (Some(_), Some(b)) if b.is_zero() => return Err(MalError::arithmetic("Divide by zero")),
+      (Some(a), Some(b)) => Number::from_ratio(a / b),
+      _ => Number::Float(self.to_f64() / other.to_f64()),
+    };
+    Ok(result)
+  }
+
+  // Applies an operation to operands of mixed representation
+  fn promote(
+    self,
+    other: Number,
+    big_op: fn(BigInt, BigInt) -> BigInt,
+    ratio_op: fn(BigRational, BigRational) -> BigRational,
+    float_op: fn(f64, f64) -> f64,
+  ) -> Number {
+    if let (Some(a), Some(b)) = (self.to_bigint(), other.to_bigint()) {
+      return Number::from_bigint(big_op(a, b));
+    }
+    match (self.to_ratio(), other.to_ratio()) {
+      (Some(a), Some(b)) => Number::from_ratio(ratio_op(a, b)),
+      _ => Number::Float(float_op(self.to_f64(), other.to_f64())),
+    }
+  }
+
+  pub fn compare(&self, other: &Number) -> Option<Ordering> {
+    match (self, other) {
+      (Number::Int(a), Number::Int(b)) => Some(a.cmp(b)),
+      (a, b) => match (a.to_ratio(), b.to_ratio()) {
+        (Some(a), Some(b)) => Some(a.cmp(&b)),
+        _ => a.to_f64().partial_cmp(&b.to_f64()),
+      },
+    }
+  }
+
+  pub fn floor(self) -> Number {
+    match self {
+      Number::Float(f) => Number::Float(f.floor()),
+      Number::Ratio(n) => Number::from_bigint(n.floor().to_integer()),
+      n => n,
+    }
+  }
+
+  pub fn ceil(self) -> Number {
+    match self {
+      Number::Float(f) => Number::Float(f.ceil()),
+      Number::Ratio(n) => Number::from_bigint(n.ceil().to_integer()),
+      n => n,
+    }
+  }
+
+  // Rounds half away from zero to the nearest integer
+  pub fn round(self) -> Number {
+    match self {
+      Number::Float(f) => match BigInt::from_f64(f.round()) {
+        Some(n) => Number::from_bigint(n),
+        None => Number::Float(f),
+      },
+      Number::Ratio(n) => Number::from_bigint(n.round().to_integer()),
+      n => n,
+    }
+  }
+
+  pub fn sqrt(self) -> Number {
+    Number::Float(self.to_f64().sqrt())
+  }
+
+  pub fn pow(self, exponent: Number) -> Number {
+    Number::Float(self.to_f64().powf(exponent.to_f64()))
+  }
+
+  pub fn numerator(self) -> Option<Number> {
+    match self {
+      Number::Ratio(n) => Some(Number::from_bigint(n.numer().to_owned())),
+      Number::Float(_) => None,
+      n => Some(n),
+    }
+  }
+
+  pub fn denominator(self) -> Option<Number> {
+    match self {
+      Number::Ratio(n) => Some(Number::from_bigint(n.denom().to_owned())),
+      Number::Float(_) => None,
+      _ => Some(Number::Int(1)),
+    }
+  }
+}
@@ -2,43 +2,119 @@ extern crate regex;
 
 use regex::Regex;
 
+use crate::limits;
 use crate::types::*;
 
 pub fn print_str(input: &MalType, print_readably: bool) -> String {
-  match input {
-    MalType::Nil => String::from("nil"),
-    MalType::False => String::from("false"),
-    MalType::True => String::from("true"),
-    MalType::Number(num) => num.to_string(),
-    MalType::Symbol(sym) => sym.to_string(),
-    MalType::String(s) => {
-      if print_readably {
-        print_string(s)
-      } else {
-        s.to_owned()
+  let mut printer = Printer {
+    output: String::new(),
+    print_readably,
+    limited: false,
+  };
+  // Only counting against the memory limit can fail
+  printer.print(input).unwrap();
+  printer.output
+}
+
+// Prints like print_str, counting the output against the memory limit as it
+// grows. A value sharing structure can print much larger than it is, so this
+// fails before building more than the limit allows.
+pub fn print_str_limited(input: &MalType, print_readably: bool) -> Result<String, MalError> {
+  let mut printer = Printer {
+    output: String::new(),
+    print_readably,
+    limited: true,
+  };
+  printer.print(input)?;
+  Ok(printer.output)
+}
+
+struct Printer {
+  output: String,
+  print_readably: bool,
+  limited: bool,
+}
+
+impl Printer {
+  fn push(&mut self, text: &str) -> Result<(), MalError> {
+    if self.limited {
+      limits::allocate(text.len())?;
+    }
+    self.output.push_str(text);
+    Ok(())
+  }
+
+  fn print(&mut self, input: &MalType) -> Result<(), MalError> {
+    match input {
+      MalType::Nil => self.push("nil"),
+      MalType::False => self.push("false"),
+      MalType::True => self.push("true"),
+      MalType::Number(num) => self.push(&num.to_string()),
+      MalType::BigInt(num) => self.push(&num.to_string()),
+      MalType::Ratio(num) => self.push(&num.to_string()),
+      MalType::Float(num) => self.push(&print_float(*num)),
+      MalType::Symbol(sym, _) | MalType::Local(sym, _, _) => self.push(&sym.name()),
+      MalType::String(s) => {
+        if self.print_readably {
+          self.push(&print_string(s))
+        } else {
+          self.push(s)
+        }
+      }
+      MalType::Keyword(s) => {
+        self.push(":")?;
+        self.push(s)
+      }
+      MalType::List(list, _, _) => self.print_list_like(list, "(", ")"),
+      MalType::Vector(list, _, _) => self.print_list_like(list, "[", "]"),
+      MalType::HashMap(map, _) => self.print_hash_map(map),
+      MalType::Function(_) => self.push("#<function>"),
+      MalType::Lambda(_) => self.push("#<function>"),
+      MalType::TailCall(_) => self.push("#<tail call>"),
+      MalType::Atom(atom, _) => {
+        self.push("(atom ")?;
+        self.print(&atom.borrow())?;
+        self.push(")")
       }
     }
-    MalType::Keyword(s) => String::from(":") + s,
-    MalType::List(list) => print_list_like(list, "(", ")", print_readably),
-    MalType::Vector(list) => print_list_like(list, "[", "]", print_readably),
-    MalType::HashMap(list) => print_list_like(list, "{", "}", print_readably),
-    MalType::Function(_) => String::from("#<function>"),
-    MalType::Lambda(_) => String::from("#<function>"),
-    MalType::Atom(atom) => {
-      format!("(atom {})", print_str(&atom.borrow(), print_readably)).to_string()
+  }
+
+  fn print_list_like(&mut self, list: &MalList, start: &str, end: &str) -> Result<(), MalError> {
+    self.push(start)?;
+    for (index, value) in list.iter().enumerate() {
+      if index > 0 {
+        self.push(" ")?;
+      }
+      self.print(value)?;
     }
+    self.push(end)
+  }
+
+  fn print_hash_map(&mut self, map: &MalHashMap) -> Result<(), MalError> {
+    self.push("{")?;
+    for (index, (key, value)) in map.iter().enumerate() {
+      if index > 0 {
+        self.push(" ")?;
+      }
+      self.print(&MalType::from(key.to_owned()))?;
+      self.push(" ")?;
+      self.print(value)?;
+    }
+    self.push("}")
   }
 }
 
-fn print_list_like(list: &Vec<MalType>, start: &str, end: &str, print_readably: bool) -> String {
-  let mut output = String::from(start);
-  output += &list
-    .iter()
-    .map(|val| print_str(val, print_readably))
-    .collect::<Vec<String>>()
-    .join(" ");
-  output += end;
-  output
+// Floats always print with a decimal point or exponent so they read back as floats
+fn print_float(num: f64) -> String {
+  if num.is_nan() {
+    String::from("##NaN")
+  } else if num.is_infinite() && num > 0.0 {
+    String::from("##Inf")
+  } else if num.is_infinite() {
+    String::from("##-Inf")
+  } else {
+    format!("{:?}", num)
+  }
 }
 
 const ESCAPED_QUOTES_PATTERN: &str = r#"\\'"#;
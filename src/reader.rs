@@ -1,32 +1,75 @@
 extern crate regex;
 
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::Zero;
 use regex::Regex;
+use std::rc::Rc;
 
+use crate::limits;
+use crate::number::Number;
+use crate::symbol;
 use crate::types::*;
 
 const TOKEN_PATTERN: &str =
   r#"[\s,]*(~@|[\[\]{}()'`~^@]|"(?:\\.|[^\\"])*"|;.*|[^\s\[\]{}('"`,;)]*)"#;
-const NUMBER_PATTERN: &str = r#"^\-?[\d\.]+$"#;
+const INTEGER_PATTERN: &str = r#"^-?\d+$"#;
+const RATIO_PATTERN: &str = r#"^(-?\d+)/(\d+)$"#;
+const FLOAT_PATTERN: &str = r#"^-?(\d+\.\d*|\.\d+|\d+)([eE][-+]?\d+)?$"#;
 
 pub fn read_str(input: String) -> MalResult {
-  let mut reader = Reader::new(tokenize(input));
+  let mut reader = Reader::new(tokenize(input, None));
   read_form(&mut reader)
 }
 
+// Reads every form in a file, wrapped in (do ... nil) so it can be evaluated at once
+pub fn read_file(input: String, file: &str) -> MalResult {
+  let mut reader = Reader::new(tokenize(input, Some(file)));
+  let mut forms = vec![MalType::Symbol(symbol::DO, None)];
+  while reader.peek().is_some() {
+    match read_form(&mut reader) {
+      Ok(form) => forms.push(form),
+      Err(err) => match err.reason() {
+        MalErrorReason::BlankLine => {
+          reader.next();
+        }
+        _ => return Err(err),
+      },
+    }
+  }
+  forms.push(MalType::Nil);
+  Ok(MalType::List(forms.into(), None, None))
+}
+
+pub struct Token {
+  pub value: String,
+  pub span: Rc<Span>,
+}
+
 pub struct Reader {
-  tokens: Vec<String>,
+  tokens: Vec<Token>,
   position: usize,
 }
 
 impl Reader {
-  pub fn new(tokens: Vec<String>) -> Reader {
+  pub fn new(tokens: Vec<Token>) -> Reader {
     Reader {
-      tokens: tokens,
+      tokens,
       position: 0,
     }
   }
 
+  // Span of the current token, or of the last one once input is exhausted
+  pub fn span(&self) -> Option<Rc<Span>> {
+    self
+      .tokens
+      .get(self.position)
+      .or_else(|| self.tokens.last())
+      .map(|token| token.span.clone())
+  }
+
   // Return token at current position and increments position
+  #[allow(clippy::should_implement_trait)]
   pub fn next(&mut self) -> Option<String> {
     let current = self.peek();
     if self.position < self.tokens.len() {
@@ -37,22 +80,40 @@ impl Reader {
 
   // Return current token
   pub fn peek(&self) -> Option<String> {
-    if let Some(token) = self.tokens.get(self.position) {
-      Some(token.to_string())
-    } else {
-      None
-    }
+    self
+      .tokens
+      .get(self.position)
+      .map(|token| token.value.to_string())
   }
 }
 
-fn tokenize(input: String) -> Vec<String> {
+fn tokenize(input: String, file: Option<&str>) -> Vec<Token> {
   let pattern = Regex::new(TOKEN_PATTERN).unwrap();
+  let file: Option<Rc<str>> = file.map(Rc::from);
+  let source: Rc<str> = Rc::from(input.as_str());
   let mut tokens = vec![];
+  let (mut line, mut line_start, mut scanned) = (1, 0, 0);
   for capture in pattern.captures_iter(&input) {
-    if capture[1].starts_with(";") {
+    let token = capture.get(1).unwrap();
+    if token.as_str().starts_with(";") {
       continue;
     }
-    tokens.push(capture[1].to_string());
+    for (offset, c) in input[scanned..token.start()].char_indices() {
+      if c == '\n' {
+        line += 1;
+        line_start = scanned + offset + 1;
+      }
+    }
+    scanned = token.start();
+    tokens.push(Token {
+      value: token.as_str().to_string(),
+      span: Rc::new(Span {
+        file: file.clone(),
+        source: source.clone(),
+        line,
+        col: input[line_start..token.start()].chars().count() + 1,
+      }),
+    });
   }
   tokens
 }
@@ -69,19 +130,19 @@ fn read_form(reader: &mut Reader) -> MalResult {
         '"' => read_string(reader),
         ':' => read_keyword(reader),
         '^' => read_with_meta(reader),
-        '\'' => read_quote(reader, "quote"),
-        '`' => read_quote(reader, "quasiquote"),
-        '@' => read_quote(reader, "deref"),
+        '\'' => read_quote(reader, symbol::QUOTE),
+        '`' => read_quote(reader, symbol::QUASIQUOTE),
+        '@' => read_quote(reader, symbol::DEREF),
         '~' => {
           if let Some('@') = chars.next() {
-            read_quote(reader, "splice-unquote")
+            read_quote(reader, symbol::SPLICE_UNQUOTE)
           } else {
-            read_quote(reader, "unquote")
+            read_quote(reader, symbol::UNQUOTE)
           }
         }
         ';' => {
           reader.next();
-          return Err(MalError::blank_line());
+          Err(MalError::blank_line())
         }
         _ => read_atom(reader),
       }
@@ -94,28 +155,50 @@ fn read_form(reader: &mut Reader) -> MalResult {
 }
 
 fn read_atom(reader: &mut Reader) -> MalResult {
+  let span = reader.span();
   let token = reader.next().unwrap();
-  let pattern = Regex::new(NUMBER_PATTERN).unwrap();
-  let value = if pattern.is_match(&token) {
-    MalType::Number(token.parse::<i64>().unwrap_or(0))
+  let integer_pattern = Regex::new(INTEGER_PATTERN).unwrap();
+  let ratio_pattern = Regex::new(RATIO_PATTERN).unwrap();
+  let float_pattern = Regex::new(FLOAT_PATTERN).unwrap();
+  let value = if integer_pattern.is_match(&token) {
+    match token.parse::<i64>() {
+      Ok(n) => MalType::Number(n),
+      Err(_) => MalType::BigInt(token.parse::<BigInt>().unwrap()),
+    }
+  } else if let Some(caps) = ratio_pattern.captures(&token) {
+    let numerator = caps[1].parse::<BigInt>().unwrap();
+    let denominator = caps[2].parse::<BigInt>().unwrap();
+    if denominator.is_zero() {
+      return Err(MalError::arithmetic("Divide by zero").with_span(span));
+    }
+    Number::from_ratio(BigRational::new(numerator, denominator)).to_mal()
+  } else if float_pattern.is_match(&token) {
+    MalType::Float(token.parse::<f64>().unwrap_or(0.0))
   } else {
     match token.as_ref() {
+      "##NaN" => MalType::Float(f64::NAN),
+      "##Inf" => MalType::Float(f64::INFINITY),
+      "##-Inf" => MalType::Float(f64::NEG_INFINITY),
       "nil" => MalType::Nil,
       "true" => MalType::True,
       "false" => MalType::False,
-      _ => MalType::Symbol(token),
+      _ => {
+        limits::allocate(token.len()).map_err(|err| err.with_span(span.clone()))?;
+        MalType::Symbol(Symbol::intern(&token), span)
+      }
     }
   };
   Ok(value)
 }
 
 fn read_inner_list(reader: &mut Reader, end: char) -> Result<Vec<MalType>, MalError> {
+  let span = reader.span();
   reader.next(); // Consume opening
   let mut list = vec![];
   loop {
     let token = match reader.peek() {
       Some(t) => t,
-      None => return Err(MalError::unexpected_eof()),
+      None => return Err(MalError::unexpected_eof().with_span(span)),
     };
     if let Some(c) = token.chars().next() {
       if c == end {
@@ -124,29 +207,44 @@ fn read_inner_list(reader: &mut Reader, end: char) -> Result<Vec<MalType>, MalEr
         list.push(read_form(reader)?);
       }
     } else {
-      return Err(MalError::unexpected_eof());
+      return Err(MalError::unexpected_eof().with_span(span));
     }
   }
   reader.next(); // consume closing
+  limits::allocate_items(list.len()).map_err(|err| err.with_span(span))?;
   Ok(list)
 }
 
 fn read_list(reader: &mut Reader) -> MalResult {
+  let span = reader.span();
   let list = read_inner_list(reader, ')')?;
-  Ok(MalType::List(list))
+  Ok(MalType::List(list.into(), span, None))
 }
 
 fn read_vector(reader: &mut Reader) -> MalResult {
+  let span = reader.span();
   let list = read_inner_list(reader, ']')?;
-  Ok(MalType::Vector(list))
+  Ok(MalType::Vector(list.into(), span, None))
 }
 
 fn read_hashmap(reader: &mut Reader) -> MalResult {
+  let span = reader.span();
   let list = read_inner_list(reader, '}')?;
-  Ok(MalType::HashMap(list))
+  if !list.len().is_multiple_of(2) {
+    return Err(MalError::odd_hash_map_literal().with_span(span));
+  }
+  let mut map = MalHashMap::new();
+  for pair in list.chunks(2) {
+    match pair[0].to_hash_key() {
+      Some(key) => map.insert(key, pair[1].to_owned()),
+      None => return Err(MalError::invalid_hash_key().with_span(span)),
+    };
+  }
+  Ok(MalType::HashMap(map, None))
 }
 
 fn read_string(reader: &mut Reader) -> MalResult {
+  let span = reader.span();
   let token = reader.next().unwrap();
   let mut chars = token.chars();
   if chars.next().unwrap() != '"' {
@@ -156,11 +254,12 @@ fn read_string(reader: &mut Reader) -> MalResult {
   loop {
     match chars.next() {
       Some('"') => break,
-      Some('\\') => out.push(unescape_char(chars.next())?),
+      Some('\\') => out.push(unescape_char(chars.next()).map_err(|e| e.with_span(span.clone()))?),
       Some(c) => out.push(c),
-      None => return Err(MalError::unexpected_end_of_string()),
+      None => return Err(MalError::unexpected_end_of_string().with_span(span)),
     }
   }
+  limits::allocate(out.len()).map_err(|err| err.with_span(span))?;
   Ok(MalType::String(out))
 }
 
@@ -173,20 +272,24 @@ fn unescape_char(input: Option<char>) -> Result<char, MalError> {
 }
 
 fn read_keyword(reader: &mut Reader) -> MalResult {
+  let span = reader.span();
   let token = reader.next().unwrap();
+  limits::allocate(token.len()).map_err(|err| err.with_span(span))?;
   Ok(MalType::Keyword(token[1..].to_string()))
 }
 
-fn read_quote(reader: &mut Reader, label: &str) -> MalResult {
+fn read_quote(reader: &mut Reader, label: Symbol) -> MalResult {
+  let span = reader.span();
   reader.next().unwrap(); // Consume quote character
-  let list = vec![MalType::Symbol(label.to_string()), read_form(reader)?];
-  Ok(MalType::List(list))
+  let list = vec![MalType::Symbol(label, None), read_form(reader)?];
+  Ok(MalType::List(list.into(), span, None))
 }
 
 fn read_with_meta(reader: &mut Reader) -> MalResult {
+  let span = reader.span();
   reader.next().unwrap(); // Consume meta character
   let metadata = read_form(reader)?;
   let value = read_form(reader)?;
-  let list = vec![MalType::Symbol("with-meta".to_string()), value, metadata];
-  Ok(MalType::List(list))
+  let list = vec![MalType::Symbol(symbol::WITH_META, None), value, metadata];
+  Ok(MalType::List(list.into(), span, None))
 }
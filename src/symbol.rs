@@ -0,0 +1,156 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+// An interned symbol name. Symbols compare and hash as their id, so env lookups
+// and special form dispatch never touch the name itself.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+// Names interned up front so the evaluators can match on them as constants.
+// The ids below must follow the order of WELL_KNOWN.
+pub const DEF: Symbol = Symbol(0);
+pub const LET: Symbol = Symbol(1);
+pub const DO: Symbol = Symbol(2);
+pub const IF: Symbol = Symbol(3);
+pub const FN: Symbol = Symbol(4);
+pub const QUOTE: Symbol = Symbol(5);
+pub const QUASIQUOTE: Symbol = Symbol(6);
+pub const DEFMACRO: Symbol = Symbol(7);
+pub const MACROEXPAND: Symbol = Symbol(8);
+pub const TRY: Symbol = Symbol(9);
+pub const CATCH: Symbol = Symbol(10);
+pub const UNQUOTE: Symbol = Symbol(11);
+pub const SPLICE_UNQUOTE: Symbol = Symbol(12);
+pub const CONCAT: Symbol = Symbol(13);
+pub const CONS: Symbol = Symbol(14);
+pub const WITH_META: Symbol = Symbol(15);
+pub const DEREF: Symbol = Symbol(16);
+pub const AMPERSAND: Symbol = Symbol(17);
+// fn* whose body the analyzer has already resolved. It prints as fn* but
+// can't be produced by interning, so it never appears in user code.
+pub const FN_ANALYZED: Symbol = Symbol(18);
+
+const WELL_KNOWN: &[&str] = &[
+  "def!",
+  "let*",
+  "do",
+  "if",
+  "fn*",
+  "quote",
+  "quasiquote",
+  "defmacro!",
+  "macroexpand",
+  "try*",
+  "catch*",
+  "unquote",
+  "splice-unquote",
+  "concat",
+  "cons",
+  "with-meta",
+  "deref",
+  "&",
+];
+
+struct Interner {
+  names: Vec<Arc<str>>,
+  ids: HashMap<Arc<str>, Symbol>,
+}
+
+impl Interner {
+  fn new() -> Interner {
+    let mut interner = Interner {
+      names: vec![],
+      ids: HashMap::new(),
+    };
+    for name in WELL_KNOWN {
+      interner.intern(name);
+    }
+    interner.names.push(Arc::from("fn*"));
+    interner
+  }
+
+  fn intern(&mut self, name: &str) -> Symbol {
+    if let Some(sym) = self.ids.get(name) {
+      return *sym;
+    }
+    let name: Arc<str> = Arc::from(name);
+    let sym = Symbol(self.names.len() as u32);
+    self.names.push(name.clone());
+    self.ids.insert(name, sym);
+    sym
+  }
+}
+
+// Names are never freed and ids never reused, so a symbol stays valid however
+// long it is kept, even past the Interpreter that read it
+lazy_static! {
+  static ref INTERNER: Mutex<Interner> = Mutex::new(Interner::new());
+}
+
+// The names each thread has used, so only the first intern or lookup of a
+// name on a thread takes the lock
+#[derive(Default)]
+struct Cache {
+  names: Vec<Option<Arc<str>>>,
+  ids: HashMap<Arc<str>, Symbol>,
+}
+
+thread_local! {
+  static CACHE: RefCell<Cache> = RefCell::new(Cache::default());
+}
+
+impl Symbol {
+  pub fn intern(name: &str) -> Symbol {
+    CACHE.with(|cache| {
+      let mut cache = cache.borrow_mut();
+      if let Some(sym) = cache.ids.get(name) {
+        return *sym;
+      }
+      let sym = INTERNER.lock().unwrap().intern(name);
+      cache.ids.insert(Arc::from(name), sym);
+      sym
+    })
+  }
+
+  pub fn name(self) -> Arc<str> {
+    let index = self.0 as usize;
+    CACHE.with(|cache| {
+      let mut cache = cache.borrow_mut();
+      if let Some(Some(name)) = cache.names.get(index) {
+        return name.clone();
+      }
+      let name = INTERNER.lock().unwrap().names[index].clone();
+      if cache.names.len() <= index {
+        cache.names.resize(index + 1, None);
+      }
+      cache.names[index] = Some(name.clone());
+      name
+    })
+  }
+}
+
+impl From<&str> for Symbol {
+  fn from(name: &str) -> Symbol {
+    Symbol::intern(name)
+  }
+}
+
+impl From<&String> for Symbol {
+  fn from(name: &String) -> Symbol {
+    Symbol::intern(name)
+  }
+}
+
+impl fmt::Display for Symbol {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.name())
+  }
+}
+
+impl fmt::Debug for Symbol {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.name())
+  }
+}
@@ -1,99 +1,238 @@
+use num_bigint::BigInt;
+use num_rational::BigRational;
 use std::cell::RefCell;
 use std::rc::Rc;
 use std::{error, fmt};
 
-use crate::core::eval_func;
+use crate::compiler::Chunk;
+use crate::core::trampoline;
 use crate::env::*;
+use crate::printer::print_str;
+pub use crate::symbol::Symbol;
 
 #[derive(Debug, Clone)]
 pub enum MalType {
   Nil,
   True,
   False,
-  Symbol(String),
+  Symbol(Symbol, Option<Rc<Span>>),
+  // A local variable resolved by the analyzer to its frame depth and slot
+  Local(Symbol, usize, usize),
   Number(i64),
+  BigInt(BigInt),
+  Ratio(BigRational),
+  Float(f64),
   String(String),
   Keyword(String),
-  List(Vec<MalType>),
-  Vector(Vec<MalType>),
-  HashMap(Vec<MalType>),
+  List(MalList, Option<Rc<Span>>, Meta),
+  Vector(MalList, Option<Rc<Span>>, Meta),
+  HashMap(MalHashMap, Meta),
   Function(MalFunc),
   Lambda(MalLambda),
-  Atom(Rc<RefCell<MalType>>),
+  Atom(Rc<RefCell<MalType>>, Meta),
+  // Returned by a native function ending with a call, so the evaluator can
+  // make it without growing the Rust stack. MAL code never sees one.
+  TailCall(Box<TailCall>),
 }
 
 impl MalType {
   pub fn atom(value: MalType) -> MalType {
-    MalType::Atom(Rc::new(RefCell::new(value)))
+    MalType::Atom(Rc::new(RefCell::new(value)), None)
   }
 
+  // Calls `func` with the atom's value and `args`, as a tail call whose
+  // result becomes the atom's new value
   pub fn swap(&mut self, func: MalType, args: &mut Vec<MalType>) -> MalResult {
     match self {
-      MalType::Atom(ref atom) => {
+      MalType::Atom(ref atom, _) => {
         args.insert(0, atom.borrow().to_owned());
-        let result = eval_func(func, args)?;
-        atom.replace(result.clone());
-        Ok(result)
+        let atom = atom.clone();
+        Ok(MalType::tail_call_then(
+          func,
+          args.to_owned(),
+          move |result| {
+            atom.replace(result.clone());
+            Ok(result)
+          },
+        ))
       }
-      _ => return Err(MalError::wrong_arguments("Not an atom")),
+      _ => Err(MalError::wrong_arguments("Not an atom")),
     }
   }
 
-  pub fn is_list(&self) -> bool {
+  pub fn tail_call(func: MalType, args: Vec<MalType>) -> MalType {
+    MalType::TailCall(Box::new(TailCall {
+      func,
+      args,
+      then: None,
+    }))
+  }
+
+  // Tail call whose result is passed through `then`
+  pub fn tail_call_then<F>(func: MalType, args: Vec<MalType>, then: F) -> MalType
+  where
+    F: Fn(MalType) -> MalResult + 'static,
+  {
+    MalType::TailCall(Box::new(TailCall {
+      func,
+      args,
+      then: Some(Rc::new(then)),
+    }))
+  }
+
+  // Name of the value's type as used in error messages
+  pub fn type_name(&self) -> &'static str {
     match self {
-      MalType::List(_) => true,
-      _ => false,
+      MalType::Nil => "nil",
+      MalType::True | MalType::False => "boolean",
+      MalType::Symbol(_, _) | MalType::Local(..) => "symbol",
+      MalType::Number(_) | MalType::BigInt(_) => "integer",
+      MalType::Ratio(_) => "ratio",
+      MalType::Float(_) => "float",
+      MalType::String(_) => "string",
+      MalType::Keyword(_) => "keyword",
+      MalType::List(_, _, _) => "list",
+      MalType::Vector(_, _, _) => "vector",
+      MalType::HashMap(_, _) => "hash-map",
+      MalType::Function(_) | MalType::Lambda(_) => "function",
+      MalType::Atom(_, _) => "atom",
+      MalType::TailCall(_) => "tail call",
     }
   }
 
+  pub fn is_list(&self) -> bool {
+    matches!(self, MalType::List(_, _, _))
+  }
+
   pub fn is_list_or_vector(&self) -> bool {
-    match self {
-      MalType::List(_) => true,
-      MalType::Vector(_) => true,
-      _ => false,
-    }
+    matches!(self, MalType::List(_, _, _) | MalType::Vector(_, _, _))
   }
   pub fn is_map(&self) -> bool {
+    matches!(self, MalType::HashMap(_, _))
+  }
+
+  pub fn is_vector(&self) -> bool {
+    matches!(self, MalType::Vector(_, _, _))
+  }
+
+  pub fn is_symbol(&self) -> bool {
+    matches!(self, MalType::Symbol(_, _))
+  }
+
+  pub fn is_keyword(&self) -> bool {
+    matches!(self, MalType::Keyword(_))
+  }
+
+  pub fn is_string(&self) -> bool {
+    matches!(self, MalType::String(_))
+  }
+
+  pub fn is_number(&self) -> bool {
+    matches!(
+      self,
+      MalType::Number(_) | MalType::BigInt(_) | MalType::Ratio(_) | MalType::Float(_)
+    )
+  }
+
+  pub fn is_function(&self) -> bool {
     match self {
-      MalType::HashMap(_) => true,
+      MalType::Function(_) => true,
+      MalType::Lambda(lambda) => !lambda.is_macro,
       _ => false,
     }
   }
 
-  pub fn is_truthy(&self) -> bool {
+  // Metadata of the value, nil if it has none
+  pub fn meta(&self) -> MalType {
     match self {
-      MalType::False => false,
-      MalType::Nil => false,
-      _ => true,
+      MalType::Function(func) => *func.meta.to_owned(),
+      MalType::Lambda(lambda) => *lambda.meta.to_owned(),
+      MalType::List(_, _, meta)
+      | MalType::Vector(_, _, meta)
+      | MalType::HashMap(_, meta)
+      | MalType::Atom(_, meta) => meta.as_deref().cloned().unwrap_or(MalType::Nil),
+      _ => MalType::Nil,
     }
   }
 
+  // Copy of the value with `meta` as its metadata. An atom's copy still
+  // refers to the same state.
+  pub fn with_meta(&self, meta: MalType) -> MalResult {
+    let value = match self.to_owned() {
+      MalType::Function(func) => MalType::Function(MalFunc {
+        meta: Box::new(meta),
+        ..func
+      }),
+      MalType::Lambda(lambda) => MalType::Lambda(MalLambda {
+        meta: Box::new(meta),
+        ..lambda
+      }),
+      MalType::List(list, span, _) => MalType::List(list, span, Some(Rc::new(meta))),
+      MalType::Vector(list, span, _) => MalType::Vector(list, span, Some(Rc::new(meta))),
+      MalType::HashMap(map, _) => MalType::HashMap(map, Some(Rc::new(meta))),
+      MalType::Atom(atom, _) => MalType::Atom(atom, Some(Rc::new(meta))),
+      value => {
+        return Err(MalError::wrong_arguments(&format!(
+          "Metadata is not supported on {}",
+          value.type_name()
+        )))
+      }
+    };
+    Ok(value)
+  }
+
+  pub fn is_truthy(&self) -> bool {
+    !matches!(self, MalType::False | MalType::Nil)
+  }
+
   pub fn is_nil(&self) -> bool {
+    matches!(self, MalType::Nil)
+  }
+
+  pub fn is_macro(&self) -> bool {
     match self {
-      MalType::Nil => true,
+      MalType::Lambda(lambda) => lambda.is_macro,
       _ => false,
     }
   }
 
   pub fn is_atom(&self) -> bool {
+    matches!(self, MalType::Atom(_, _))
+  }
+
+  pub fn is_pair(&self) -> bool {
     match self {
-      MalType::Atom(_) => true,
+      MalType::List(vec, _, _) => !vec.is_empty(),
+      MalType::Vector(vec, _, _) => !vec.is_empty(),
       _ => false,
     }
   }
 
-  pub fn is_pair(&self) -> bool {
+  // Names an anonymous lambda after the symbol it is first bound to by def!
+  pub fn with_name(self, name: &str) -> MalType {
     match self {
-      MalType::List(vec) => vec.len() > 0,
-      MalType::Vector(vec) => vec.len() > 0,
-      _ => false,
+      MalType::Lambda(lambda) if lambda.name.is_none() => MalType::Lambda(MalLambda {
+        name: Some(name.to_string()),
+        ..lambda
+      }),
+      value => value,
+    }
+  }
+
+  // Where a list, vector or symbol was read from, if it came from the reader
+  pub fn span(&self) -> Option<Rc<Span>> {
+    match self {
+      MalType::List(_, span, _) | MalType::Vector(_, span, _) | MalType::Symbol(_, span) => {
+        span.to_owned()
+      }
+      _ => None,
     }
   }
 
-  pub fn list_value(&self) -> Option<Vec<MalType>> {
+  pub fn list_value(&self) -> Option<MalList> {
     match self {
-      MalType::List(list) => Some(list.to_owned()),
-      MalType::Vector(list) => Some(list.to_owned()),
+      MalType::List(list, _, _) => Some(list.to_owned()),
+      MalType::Vector(list, _, _) => Some(list.to_owned()),
       _ => None,
     }
   }
@@ -105,9 +244,9 @@ impl MalType {
     }
   }
 
-  pub fn symbol_value(&self) -> Option<String> {
+  pub fn symbol_value(&self) -> Option<Symbol> {
     match self {
-      MalType::Symbol(s) => Some(s.to_owned()),
+      MalType::Symbol(s, _) => Some(*s),
       _ => None,
     }
   }
@@ -119,6 +258,28 @@ impl MalType {
     }
   }
 
+  pub fn lambda_value(&self) -> Option<MalLambda> {
+    match self {
+      MalType::Lambda(lambda) => Some(lambda.to_owned()),
+      _ => None,
+    }
+  }
+
+  pub fn hash_map_value(&self) -> Option<MalHashMap> {
+    match self {
+      MalType::HashMap(map, _) => Some(map.to_owned()),
+      _ => None,
+    }
+  }
+
+  pub fn to_hash_key(&self) -> Option<MalHashKey> {
+    match self {
+      MalType::String(s) => Some(MalHashKey::String(s.to_owned())),
+      MalType::Keyword(s) => Some(MalHashKey::Keyword(s.to_owned())),
+      _ => None,
+    }
+  }
+
   pub fn function_value(&self) -> Option<MalFunc> {
     match self {
       MalType::Function(func) => Some(func.to_owned()),
@@ -134,32 +295,155 @@ impl MalType {
     }
   }
 
-  pub fn is_symbol_named(&self, sym: &str) -> bool {
+  pub fn is_symbol_named(&self, sym: Symbol) -> bool {
     match self {
-      MalType::Symbol(s) => s == sym,
+      MalType::Symbol(s, _) => *s == sym,
       _ => false,
     }
   }
 }
 
-pub type CoreFunction = fn(&mut Vec<MalType>, Option<Env>) -> MalResult;
+// Only strings and keywords may be used as hash-map keys
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MalHashKey {
+  String(String),
+  Keyword(String),
+}
+
+impl From<MalHashKey> for MalType {
+  fn from(key: MalHashKey) -> MalType {
+    match key {
+      MalHashKey::String(s) => MalType::String(s),
+      MalHashKey::Keyword(s) => MalType::Keyword(s),
+    }
+  }
+}
+
+// Collections are persistent, so cloning a value shares its structure and
+// cons, rest, conj and assoc don't copy the whole collection
+pub type MalList = im_rc::Vector<MalType>;
+
+pub type MalHashMap = im_rc::HashMap<MalHashKey, MalType>;
+
+// Metadata given to a collection or atom by with-meta, None if it has none
+pub type Meta = Option<Rc<MalType>>;
+
+pub type CoreFunction = fn(&mut Vec<MalType>) -> MalResult;
+
+// Native function body. Closures can capture host state such as handles or counters.
+pub type NativeFunction = Rc<dyn Fn(&mut Vec<MalType>) -> MalResult>;
+
+// Number of arguments a function accepts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Arity {
+  pub min: usize,
+  pub max: Option<usize>,
+}
+
+impl Arity {
+  pub fn exact(n: usize) -> Arity {
+    Arity {
+      min: n,
+      max: Some(n),
+    }
+  }
+
+  pub fn at_least(n: usize) -> Arity {
+    Arity { min: n, max: None }
+  }
+
+  pub fn between(min: usize, max: usize) -> Arity {
+    Arity {
+      min,
+      max: Some(max),
+    }
+  }
+
+  pub fn any() -> Arity {
+    Arity::at_least(0)
+  }
+
+  pub fn accepts(&self, count: usize) -> bool {
+    count >= self.min && self.max.is_none_or(|max| count <= max)
+  }
+}
+
+impl fmt::Display for Arity {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self.max {
+      Some(max) if max == self.min => write!(f, "{}", max),
+      Some(max) => write!(f, "{} to {}", self.min, max),
+      None => write!(f, "at least {}", self.min),
+    }
+  }
+}
 
 #[derive(Clone)]
 pub struct MalFunc {
-  pub func: CoreFunction,
-  pub env: Option<Env>,
+  pub func: NativeFunction,
+  pub name: String,
+  pub arity: Arity,
+  pub meta: Box<MalType>,
+}
+
+impl MalFunc {
+  pub fn new<F>(name: &str, arity: Arity, func: F) -> MalFunc
+  where
+    F: Fn(&mut Vec<MalType>) -> MalResult + 'static,
+  {
+    MalFunc {
+      func: Rc::new(func),
+      name: name.to_string(),
+      arity,
+      meta: Box::new(MalType::Nil),
+    }
+  }
+
+  // Calls the function, making any tail call it ends with
+  pub fn call(&self, args: &mut Vec<MalType>) -> MalResult {
+    trampoline(self.call_tail(args)?)
+  }
+
+  // Calls the function, leaving a tail call it ends with for the caller to make
+  pub fn call_tail(&self, args: &mut Vec<MalType>) -> MalResult {
+    if !self.arity.accepts(args.len()) {
+      return Err(MalError::wrong_arity(&self.name, args.len(), self.arity));
+    }
+    (self.func)(args)
+  }
+}
+
+// Turns the result of calling `func` into the result of the native function
+pub type Continuation = Rc<dyn Fn(MalType) -> MalResult>;
+
+#[derive(Clone)]
+pub struct TailCall {
+  pub func: MalType,
+  pub args: Vec<MalType>,
+  pub then: Option<Continuation>,
+}
+
+impl fmt::Debug for TailCall {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "#<tail call {:?}>", self.func)
+  }
 }
 
 #[derive(Clone)]
 pub struct MalLambda {
   pub env: Env,
-  pub args: Vec<MalType>,
+  pub args: MalList,
   pub body: Vec<MalType>,
+  pub is_macro: bool,
+  pub meta: Box<MalType>,
+  pub name: Option<String>,
+  // Compiled body, for lambdas created by the VM
+  pub code: Option<Rc<Chunk>>,
 }
 
 impl fmt::Debug for MalFunc {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    write!(f, "#<function>")
+    write!(f, "#<function {}>", self.name)
   }
 }
 
@@ -179,9 +463,16 @@ pub enum MalErrorReason {
   SymbolNotFound(String),
   NotAFunction,
   NotANumber,
+  ArithmeticError(String),
   WrongArguments(String),
   BlankLine,
+  OddHashMapLiteral,
+  InvalidHashKey,
   Generic(String),
+  Exception(Box<MalType>),
+  StackOverflow,
+  BudgetExceeded(String),
+  MemoryLimitExceeded,
 }
 
 impl fmt::Display for MalErrorReason {
@@ -192,85 +483,269 @@ impl fmt::Display for MalErrorReason {
       MalErrorReason::UnexpectedEndOfString => {
         "Unexpected end of string. Possibly unbalanced quotes".to_string()
       }
-      MalErrorReason::SymbolNotFound(sym) => format!("Symbol '{}' not found", sym),
+      MalErrorReason::SymbolNotFound(sym) => format!("'{}' not found", sym),
       MalErrorReason::NotAFunction => "Expected function".to_string(),
       MalErrorReason::NotANumber => "Expected number".to_string(),
+      MalErrorReason::ArithmeticError(reason) => format!("Arithmetic error - {}", reason),
       MalErrorReason::WrongArguments(reason) => format!("Wrong arguments - {}", reason),
       MalErrorReason::BlankLine => "".to_string(),
+      MalErrorReason::OddHashMapLiteral => {
+        "Hash-map literal must contain an even number of forms".to_string()
+      }
+      MalErrorReason::InvalidHashKey => "Hash-map keys must be strings or keywords".to_string(),
       MalErrorReason::Generic(reason) => reason.to_string(),
+      MalErrorReason::Exception(value) => print_str(value, true),
+      MalErrorReason::StackOverflow => "Stack overflow - too much recursion".to_string(),
+      MalErrorReason::BudgetExceeded(reason) => format!("Budget exceeded - {}", reason),
+      MalErrorReason::MemoryLimitExceeded => "Memory limit exceeded".to_string(),
     };
     write!(f, "{}", reason)
   }
 }
 
+// Location of a token in the source it was read from. Lines and columns start at 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+  pub file: Option<Rc<str>>,
+  pub source: Rc<str>,
+  pub line: usize,
+  pub col: usize,
+}
+
+impl fmt::Display for Span {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let file = self.file.as_deref().unwrap_or("<input>");
+    let gutter = " ".repeat(self.line.to_string().len());
+    writeln!(f, "{}--> {}:{}:{}", gutter, file, self.line, self.col)?;
+    let text = self.source.lines().nth(self.line - 1).unwrap_or("");
+    // Keep tabs in the caret line so it stays aligned with the snippet
+    let indent: String = text
+      .chars()
+      .take(self.col - 1)
+      .map(|c| if c == '\t' { '\t' } else { ' ' })
+      .collect();
+    writeln!(f, "{} |", gutter)?;
+    writeln!(f, "{} | {}", self.line, text)?;
+    write!(f, "{} | {}^", gutter, indent)
+  }
+}
+
+// A lambda call that was in progress when an error was raised
+#[derive(Debug, Clone)]
+pub struct Frame {
+  pub name: Option<String>,
+  pub span: Option<Rc<Span>>,
+}
+
+impl Frame {
+  // Frame as a hash-map of :name, :file, :line and :col for catch* handlers
+  pub fn to_mal(&self) -> MalType {
+    let mut map = MalHashMap::new();
+    let name = match &self.name {
+      Some(name) => MalType::String(name.to_owned()),
+      None => MalType::Nil,
+    };
+    map.insert(MalHashKey::Keyword("name".to_string()), name);
+    if let Some(span) = &self.span {
+      let file = match &span.file {
+        Some(file) => MalType::String(file.to_string()),
+        None => MalType::Nil,
+      };
+      map.insert(MalHashKey::Keyword("file".to_string()), file);
+      map.insert(
+        MalHashKey::Keyword("line".to_string()),
+        MalType::Number(span.line as i64),
+      );
+      map.insert(
+        MalHashKey::Keyword("col".to_string()),
+        MalType::Number(span.col as i64),
+      );
+    }
+    MalType::HashMap(map, None)
+  }
+}
+
+impl fmt::Display for Frame {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "  at {}", self.name.as_deref().unwrap_or("<anonymous>"))?;
+    if let Some(span) = &self.span {
+      let file = span.file.as_deref().unwrap_or("<input>");
+      write!(f, " ({}:{}:{})", file, span.line, span.col)?;
+    }
+    Ok(())
+  }
+}
+
 #[derive(Debug, Clone)]
 pub struct MalError {
   reason: MalErrorReason,
+  span: Option<Rc<Span>>,
+  backtrace: Vec<Frame>,
 }
 
 impl MalError {
-  pub fn unexpected_eof() -> MalError {
+  fn new(reason: MalErrorReason) -> MalError {
     MalError {
-      reason: MalErrorReason::UnexpectedEOF,
+      reason,
+      span: None,
+      backtrace: vec![],
     }
   }
 
+  pub fn unexpected_eof() -> MalError {
+    MalError::new(MalErrorReason::UnexpectedEOF)
+  }
+
   pub fn unknown() -> MalError {
-    MalError {
-      reason: MalErrorReason::Unknown,
-    }
+    MalError::new(MalErrorReason::Unknown)
   }
 
   pub fn unexpected_end_of_string() -> MalError {
-    MalError {
-      reason: MalErrorReason::UnexpectedEndOfString,
-    }
+    MalError::new(MalErrorReason::UnexpectedEndOfString)
   }
 
   pub fn symbol_not_found(sym: &str) -> MalError {
-    MalError {
-      reason: MalErrorReason::SymbolNotFound(sym.to_string()),
-    }
+    MalError::new(MalErrorReason::SymbolNotFound(sym.to_string()))
+  }
+
+  pub fn stack_overflow() -> MalError {
+    MalError::new(MalErrorReason::StackOverflow)
+  }
+
+  pub fn budget_exceeded(reason: &str) -> MalError {
+    MalError::new(MalErrorReason::BudgetExceeded(reason.to_string()))
+  }
+
+  pub fn memory_limit_exceeded() -> MalError {
+    MalError::new(MalErrorReason::MemoryLimitExceeded)
   }
 
   pub fn not_a_function() -> MalError {
-    MalError {
-      reason: MalErrorReason::NotAFunction,
-    }
+    MalError::new(MalErrorReason::NotAFunction)
   }
 
   pub fn not_a_number() -> MalError {
-    MalError {
-      reason: MalErrorReason::NotANumber,
-    }
+    MalError::new(MalErrorReason::NotANumber)
+  }
+
+  pub fn arithmetic(reason: &str) -> MalError {
+    MalError::new(MalErrorReason::ArithmeticError(reason.to_string()))
   }
 
   pub fn wrong_arguments(reason: &str) -> MalError {
-    MalError {
-      reason: MalErrorReason::WrongArguments(reason.to_string()),
-    }
+    MalError::new(MalErrorReason::WrongArguments(reason.to_string()))
+  }
+
+  pub fn wrong_arity(name: &str, count: usize, expected: Arity) -> MalError {
+    MalError::wrong_arguments(&format!(
+      "wrong number of args ({}) passed to {}, expected {}",
+      count, name, expected
+    ))
+  }
+
+  pub fn wrong_type(expected: &str, value: &MalType) -> MalError {
+    MalError::wrong_arguments(&format!(
+      "Expected {} but got {}",
+      expected,
+      value.type_name()
+    ))
   }
 
   pub fn blank_line() -> MalError {
-    MalError {
-      reason: MalErrorReason::BlankLine,
-    }
+    MalError::new(MalErrorReason::BlankLine)
+  }
+
+  pub fn odd_hash_map_literal() -> MalError {
+    MalError::new(MalErrorReason::OddHashMapLiteral)
+  }
+
+  pub fn invalid_hash_key() -> MalError {
+    MalError::new(MalErrorReason::InvalidHashKey)
   }
 
   pub fn generic(reason: &str) -> MalError {
-    MalError {
-      reason: MalErrorReason::Generic(reason.to_string()),
-    }
+    MalError::new(MalErrorReason::Generic(reason.to_string()))
+  }
+
+  pub fn exception(value: MalType) -> MalError {
+    MalError::new(MalErrorReason::Exception(Box::new(value)))
   }
 
   pub fn reason(&self) -> &MalErrorReason {
     &self.reason
   }
+
+  pub fn span(&self) -> Option<&Span> {
+    self.span.as_deref()
+  }
+
+  // Records where the error happened, keeping the innermost location
+  pub fn with_span(mut self, span: Option<Rc<Span>>) -> MalError {
+    if self.span.is_none() {
+      self.span = span;
+    }
+    self
+  }
+
+  // Frames the error passed through, innermost first
+  pub fn backtrace(&self) -> &[Frame] {
+    &self.backtrace
+  }
+
+  pub fn with_frame(mut self, frame: Frame) -> MalError {
+    self.backtrace.push(frame);
+    self
+  }
+
+  pub fn backtrace_value(&self) -> MalType {
+    MalType::Vector(
+      self.backtrace.iter().map(Frame::to_mal).collect(),
+      None,
+      None,
+    )
+  }
+
+  // Value bound by catch*. Native errors are caught as their message string.
+  pub fn exception_value(&self) -> MalType {
+    match &self.reason {
+      MalErrorReason::Exception(value) => value.as_ref().to_owned(),
+      reason => MalType::String(reason.to_string()),
+    }
+  }
+
+  // Whether catch* can handle the error. Running out of fuel, time or memory
+  // ends the whole evaluation, as a handler would have none left to run with,
+  // so the host always gets these back.
+  pub fn is_catchable(&self) -> bool {
+    !matches!(
+      self.reason,
+      MalErrorReason::BudgetExceeded(_) | MalErrorReason::MemoryLimitExceeded
+    )
+  }
 }
 
 impl fmt::Display for MalError {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    write!(f, "Error: {}", self.reason)
+    write!(f, "Error: {}", self.reason)?;
+    if let Some(span) = &self.span {
+      write!(f, "\n{}", span)?;
+    }
+    for frame in &self.backtrace {
+      write!(f, "\n{}", frame)?;
+    }
+    Ok(())
+  }
+}
+
+impl From<String> for MalError {
+  fn from(reason: String) -> MalError {
+    MalError::generic(&reason)
+  }
+}
+
+impl From<&str> for MalError {
+  fn from(reason: &str) -> MalError {
+    MalError::generic(reason)
   }
 }
 
@@ -0,0 +1,342 @@
+use std::rc::Rc;
+
+use im_rc::vector;
+
+use crate::analyzer;
+use crate::compiler::{self, Chunk, Op};
+use crate::core;
+use crate::env::Env;
+use crate::limits::{self, Depth};
+use crate::symbol;
+use crate::types::*;
+
+// Runs compiled code on a value stack. Calling a compiled lambda pushes a call
+// frame instead of recursing, so only native functions calling back into MAL
+// grow the Rust stack. Locals live in the same env frames the tree-walker
+// uses, so lambdas created by either evaluator can be called by the other.
+struct Vm {
+  stack: Vec<MalType>,
+  calls: Vec<Call>,
+  handlers: Vec<Handler>,
+  // Code of the calls continuations run in
+  returning: Rc<Chunk>,
+}
+
+struct Call {
+  chunk: Rc<Chunk>,
+  ip: usize,
+  env: Env,
+  // Height of the stack below this call's values
+  base: usize,
+  // The lambda call being run, for backtraces
+  frame: Option<Frame>,
+  // Continuation of a native function's tail call, applied on returning
+  then: Option<Continuation>,
+  // Continuations live on the heap alone, so like the tree-walker's they
+  // don't count toward the depth limit
+  _depth: Option<Depth>,
+}
+
+impl Call {
+  // Span of the instruction last run
+  fn span(&self) -> Option<Rc<Span>> {
+    self
+      .ip
+      .checked_sub(1)
+      .and_then(|ip| self.chunk.spans[ip].clone())
+  }
+}
+
+// A try* whose body is running
+struct Handler {
+  call: usize,
+  stack: usize,
+  env: Env,
+  target: usize,
+  name: Symbol,
+}
+
+pub fn eval(form: MalType, env: &mut Env) -> MalResult {
+  // Forms of a top-level do are compiled one at a time, so macros defined by
+  // earlier forms expand in later ones, as when loading a file
+  if let MalType::List(list, _, _) = &form {
+    if list.len() > 1 && list[0].is_symbol_named(symbol::DO) {
+      let mut result = MalType::Nil;
+      for form in list.iter().skip(1) {
+        result = eval(form.clone(), env)?;
+      }
+      return Ok(result);
+    }
+  }
+  run(compiler::compile(form, env), env.clone())
+}
+
+// Runs `chunk` in `env` until it returns
+pub fn run(chunk: Rc<Chunk>, env: Env) -> MalResult {
+  let mut vm = Vm {
+    stack: vec![],
+    calls: vec![Call {
+      chunk,
+      ip: 0,
+      env,
+      base: 0,
+      frame: None,
+      then: None,
+      _depth: Some(Depth::enter()?),
+    }],
+    handlers: vec![],
+    returning: Rc::new(Chunk::returning()),
+  };
+  loop {
+    match vm.execute() {
+      Ok(value) => return Ok(value),
+      Err(err) => vm.unwind(err)?,
+    }
+  }
+}
+
+impl Vm {
+  fn execute(&mut self) -> MalResult {
+    loop {
+      let call = self.calls.last_mut().unwrap();
+      let op = call.chunk.code[call.ip];
+      call.ip += 1;
+      match op {
+        Op::Const(index) => self.stack.push(call.chunk.constants[index].clone()),
+        Op::GetLocal(depth, slot) => self.stack.push(call.env.get_local(depth, slot)),
+        Op::GetGlobal(sym) => self.stack.push(call.env.get(sym)?),
+        Op::Def(sym) => {
+          let value = self.stack.pop().unwrap().with_name(&sym.name());
+          call.env.set(sym, value.clone());
+          self.stack.push(value);
+        }
+        Op::DefMacro(sym) => match self.stack.pop().unwrap().with_name(&sym.name()) {
+          MalType::Lambda(lambda) => {
+            let value = MalType::Lambda(MalLambda {
+              is_macro: true,
+              ..lambda
+            });
+            call.env.set(sym, value.clone());
+            self.stack.push(value);
+          }
+          _ => return Err(MalError::wrong_arguments("Expected fn* as macro body")),
+        },
+        Op::PushFrame(index) => {
+          let names = call.chunk.frames[index].clone();
+          call.env = Env::new_frame(Some(call.env.clone()), names);
+        }
+        Op::SetSlot => call.env.push_slot(self.stack.pop().unwrap()),
+        Op::PopFrame => call.env = call.env.parent().unwrap(),
+        Op::Pop => {
+          self.stack.pop();
+        }
+        Op::Jump(target) => call.ip = target,
+        Op::JumpIfFalse(target) => {
+          if !self.stack.pop().unwrap().is_truthy() {
+            call.ip = target;
+          }
+        }
+        Op::Call(count) | Op::TailCall(count) => {
+          let args = self.stack.split_off(self.stack.len() - count);
+          let func = self.stack.pop().unwrap();
+          self.dispatch(func, args, matches!(op, Op::TailCall(_)))?;
+        }
+        Op::Return => {
+          let value = self.stack.pop().unwrap();
+          let call = self.calls.pop().unwrap();
+          self.stack.truncate(call.base);
+          match call.then {
+            Some(then) => self.resolve(then(value)?, false)?,
+            None if self.calls.is_empty() => return Ok(value),
+            None => self.stack.push(value),
+          }
+        }
+        Op::Closure(index) => {
+          let proto = &call.chunk.lambdas[index];
+          self.stack.push(MalType::Lambda(MalLambda {
+            env: call.env.clone(),
+            args: proto.params.clone(),
+            body: vec![proto.body.clone()],
+            is_macro: false,
+            meta: Box::new(MalType::Nil),
+            name: None,
+            code: Some(proto.code.clone()),
+          }));
+        }
+        Op::Vector(count) => {
+          let items = self.stack.split_off(self.stack.len() - count);
+          self
+            .stack
+            .push(MalType::Vector(items.into_iter().collect(), None, None));
+        }
+        Op::HashMap(index) => {
+          let keys = match &call.chunk.constants[index] {
+            MalType::HashMap(map, _) => map.keys().cloned(),
+            _ => unreachable!(),
+          };
+          let values = self.stack.split_off(self.stack.len() - keys.len());
+          self
+            .stack
+            .push(MalType::HashMap(keys.zip(values).collect(), None));
+        }
+        Op::Try(target, name) => {
+          let env = call.env.clone();
+          self.handlers.push(Handler {
+            call: self.calls.len() - 1,
+            stack: self.stack.len(),
+            env,
+            target,
+            name,
+          });
+        }
+        Op::EndTry => {
+          self.handlers.pop();
+        }
+        Op::Fail(index) => return Err(call.chunk.errors[index].clone()),
+        Op::ExpandMacro(index, target) => {
+          if self.stack.last().unwrap().is_macro() {
+            let lambda = self.stack.pop().unwrap();
+            let form = call.chunk.constants[index].clone();
+            let mut args: Vec<MalType> = form
+              .list_value()
+              .unwrap()
+              .into_iter()
+              .skip(1)
+              .map(analyzer::unresolve)
+              .collect();
+            let expansion = core::eval_func(lambda, &mut args)?;
+            call.ip = target;
+            let env = call.env.clone();
+            self.calls.push(Call {
+              chunk: compiler::compile(expansion, &env),
+              ip: 0,
+              env,
+              base: self.stack.len(),
+              frame: None,
+              then: None,
+              _depth: Some(Depth::enter()?),
+            });
+          }
+        }
+      }
+    }
+  }
+
+  // Calls `func`, pushing its result or a call running it. A tail call of a
+  // compiled lambda replaces the current call rather than returning to it.
+  fn dispatch(
+    &mut self,
+    func: MalType,
+    mut args: Vec<MalType>,
+    tail: bool,
+  ) -> Result<(), MalError> {
+    limits::tick()?;
+    let lambda = match func {
+      MalType::Lambda(lambda) if lambda.code.is_some() => lambda,
+      MalType::Function(func) => {
+        let value = func.call_tail(&mut args)?;
+        return self.resolve(value, tail);
+      }
+      MalType::Lambda(_) => {
+        self.stack.push(core::eval_func(func, &mut args)?);
+        return Ok(());
+      }
+      _ => return Err(MalError::not_a_function()),
+    };
+
+    let binds: Vec<Symbol> = lambda
+      .args
+      .into_iter()
+      .filter_map(|val| val.symbol_value())
+      .collect();
+    let fn_name = lambda.name.as_deref().unwrap_or("fn*");
+    let env = Env::new_with_bindings(Some(lambda.env), fn_name, binds, args.into())?;
+    let caller = self.calls.last_mut().unwrap();
+    let frame = Some(Frame {
+      name: lambda.name,
+      span: caller.span(),
+    });
+    let chunk = lambda.code.unwrap();
+    if tail {
+      self.stack.truncate(caller.base);
+      caller.chunk = chunk;
+      caller.ip = 0;
+      caller.env = env;
+      caller.frame = frame;
+    } else {
+      self.calls.push(Call {
+        chunk,
+        ip: 0,
+        env,
+        base: self.stack.len(),
+        frame,
+        then: None,
+        _depth: Some(Depth::enter()?),
+      });
+    }
+    Ok(())
+  }
+
+  // Pushes the result of a native function, making the tail call it ends with
+  // if there is one. A continuation gets a call of its own that the tail call
+  // is made from, returning its result through the continuation.
+  fn resolve(&mut self, value: MalType, tail: bool) -> Result<(), MalError> {
+    let TailCall { func, args, then } = match value {
+      MalType::TailCall(call) => *call,
+      value => {
+        self.stack.push(value);
+        return Ok(());
+      }
+    };
+    match then {
+      Some(then) => {
+        let env = self.calls.last().unwrap().env.clone();
+        self.calls.push(Call {
+          chunk: self.returning.clone(),
+          ip: 0,
+          env,
+          base: self.stack.len(),
+          frame: None,
+          then: Some(then),
+          _depth: None,
+        });
+        self.dispatch(func, args, true)
+      }
+      None => self.dispatch(func, args, tail),
+    }
+  }
+
+  // Resumes at the handler of the innermost try* with the error bound, or
+  // returns the error once it has passed through every call
+  fn unwind(&mut self, err: MalError) -> Result<(), MalError> {
+    let mut err = err.with_span(self.calls.last().unwrap().span());
+    let handler = if err.is_catchable() {
+      self.handlers.pop()
+    } else {
+      None
+    };
+    let depth = handler.as_ref().map_or(0, |handler| handler.call + 1);
+    while self.calls.len() > depth {
+      if let Some(frame) = self.calls.pop().unwrap().frame {
+        err = err.with_frame(frame);
+      }
+    }
+    let handler = match handler {
+      Some(handler) => handler,
+      None => return Err(err),
+    };
+
+    self.stack.truncate(handler.stack);
+    let mut env = Env::new_with_bindings(
+      Some(handler.env),
+      "catch*",
+      vec![handler.name],
+      vector![err.exception_value()],
+    )?;
+    env.set("*backtrace*", err.backtrace_value());
+    let call = self.calls.last_mut().unwrap();
+    call.env = env;
+    call.ip = handler.target;
+    Ok(())
+  }
+}
@@ -4,6 +4,7 @@ if [[ -z "$1" ]]; then
   echo "Missing test argument"
   echo "Expecting one of:"
   echo "  - step0_repl"
+  echo "Set MAL_ENGINE=vm to run stepA_mal on the bytecode VM"
   exit 1
 fi
 